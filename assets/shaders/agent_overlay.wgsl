@@ -0,0 +1,83 @@
+@group(0) @binding(0)
+var<storage, read> agents: array<Agent>;
+
+@group(0) @binding(1)
+var<uniform> settings: OverlaySettingsUniform;
+
+
+struct Agent {
+    position: vec2<f32>,
+    angle: f32,
+    alive: u32,
+    age: f32,
+    stuck: f32,
+    velocity: vec2<f32>,
+}
+
+struct OverlaySettingsUniform {
+    color: vec4<f32>,
+    dims: vec2<f32>,
+    size: f32,
+    fraction: f32,
+    style: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    // Position within the quad, from -1 to 1.
+    @location(0) local: vec2<f32>,
+}
+
+
+const STYLE_POINTS: u32 = 0u;
+const STYLE_HEADINGS: u32 = 1u;
+// Length of a heading line relative to the overlay size.
+const HEADING_LENGTH: f32 = 4.0;
+
+// Draws a quad for every agent in an evenly spaced subset of the agents, in texel coordinates of the display image.
+@vertex
+fn vertex(@builtin(vertex_index) vertexIdx: u32, @builtin(instance_index) instanceIdx: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertexIdx];
+
+    var out: VertexOutput;
+    // Dead agents are moved outside of the clip volume.
+    out.position = vec4<f32>(2.0, 2.0, 0.0, 1.0);
+    out.local = corner;
+
+    let agentIdx = u32(f32(instanceIdx) / settings.fraction);
+    if (agentIdx >= arrayLength(&agents)) {
+        return out;
+    }
+    let agent = agents[agentIdx];
+    if (agent.alive == 0u) {
+        return out;
+    }
+
+    var offset = corner * settings.size * 0.5;
+    if (settings.style == STYLE_HEADINGS) {
+        let forward = vec2<f32>(cos(agent.angle), sin(agent.angle));
+        let side = vec2<f32>(-forward.y, forward.x);
+        offset = forward * (corner.x * 0.5 + 0.5) * settings.size * HEADING_LENGTH + side * corner.y * max(0.5, settings.size * 0.25);
+    }
+
+    // Texel y points down, clip space y points up.
+    let uv = (agent.position + offset) / settings.dims;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (settings.style == STYLE_POINTS && dot(in.local, in.local) > 1.0) {
+        discard;
+    }
+    return settings.color;
+}
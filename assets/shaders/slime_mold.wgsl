@@ -1,12 +1,94 @@
 @group(0) @binding(0)
-var trailMap: texture_storage_2d<rgba8unorm, read_write>;
+var trailMap: texture_storage_2d<r32float, read_write>;
+
+@group(0) @binding(1)
+var<storage, read_write> deposits: array<atomic<u32>>;
+
+@group(0) @binding(2)
+var blurMap: texture_storage_2d<r32float, read_write>;
+
+// Gray-Scott chemicals (u, v) per texel, in two halves that are stepped back and forth.
+@group(0) @binding(3)
+var<storage, read_write> reaction: array<vec2<f32>>;
+
+// High dynamic range colors of the trail map, as shown on screen.
+@group(0) @binding(4)
+var displayMap: texture_storage_2d<rgba16float, write>;
+
+// Fixed point sums of the heading (x, y) and sensor disagreement of the agents in each texel, for the display modes that show them.
+@group(0) @binding(5)
+var<storage, read_write> agentSplats: array<atomic<i32>>;
 
 @group(1) @binding(0)
-var<storage, read_write> agents: array<vec3<f32>>;
+var<storage, read_write> agents: array<Agent>;
+
+@group(1) @binding(1)
+var<storage, read_write> counters: AgentCounters;
+
+@group(1) @binding(2)
+var<storage, read_write> freeList: array<u32>;
+
+@group(1) @binding(3)
+var<storage, read_write> cells: array<atomic<u32>>;
 
 @group(2) @binding(0)
 var<uniform> settings: SettingsUniform;
 
+@group(2) @binding(1)
+var parameterMap0: texture_2d<f32>;
+
+@group(2) @binding(2)
+var parameterMap1: texture_2d<f32>;
+
+@group(2) @binding(3)
+var parameterMapSampler: sampler;
+
+@group(2) @binding(4)
+var flowMap: texture_2d<f32>;
+
+@group(2) @binding(5)
+var gradientMap: texture_2d<f32>;
+
+@group(3) @binding(0)
+var<storage, read_write> dispatchArgs: DispatchArgs;
+
+// Bound in place of the dispatch arguments for the statistics reductions.
+@group(3) @binding(1)
+var<storage, read_write> stats: SimulationStats;
+
+
+struct Agent {
+    position: vec2<f32>,
+    angle: f32,
+    alive: u32,
+    age: f32,
+    stuck: f32,
+    // Distance per second covered in the last step, in texels along the axes of the trail map, or east and south on the sphere.
+    velocity: vec2<f32>,
+}
+
+struct AgentCounters {
+    count: atomic<u32>,
+    alive: atomic<u32>,
+    free: atomic<u32>,
+}
+
+// Totals of the statistics reductions, cleared before each one.
+struct SimulationStats {
+    // Fixed point sums of the trail intensity and the agent speed, each split into a low and a high word.
+    sums: array<atomic<u32>, 4>,
+    trailMax: atomic<u32>,
+    covered: atomic<u32>,
+    agents: atomic<u32>,
+    _padding: u32,
+    headings: array<atomic<u32>, 16>,
+}
+
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+}
 
 struct SettingsUniform {
     dimX: i32,
@@ -25,8 +107,64 @@ struct SettingsUniform {
     sensorOffsetDst: f32,
     sensorSize: i32,
 
-    colorA: vec4<f32>,
-    colorB: vec4<f32>,
+    brushPosition: vec2<f32>,
+    brushRadius: f32,
+    brushStrength: f32,
+
+    numAgents: u32,
+    maxAgents: u32,
+    agentsDispatchWidth: u32,
+    spawnCount: u32,
+
+    lifecycle: u32,
+    crowdLimit: u32,
+    lifetime: f32,
+    deathThreshold: f32,
+    birthThreshold: f32,
+    birthRate: f32,
+
+    occupancyLimited: u32,
+
+    diffusionKernel: u32,
+    diffusionRadius: i32,
+    diffusionSigma: f32,
+    diffusionAnisotropy: f32,
+
+    parameterMapTargets: vec2<u32>,
+    parameterMapStrengths: vec2<f32>,
+
+    sensorModel: u32,
+    sensorCount: u32,
+    sensorNoise: f32,
+
+    steeringModel: u32,
+    steeringTemperature: f32,
+    applyNewAngle: u32,
+
+    wind: vec2<f32>,
+    flowMapStrength: f32,
+    flowMapEnabled: u32,
+
+    reactionDiffusion: u32,
+    feedRate: f32,
+    killRate: f32,
+    diffusionRateU: f32,
+    diffusionRateV: f32,
+    reactionSpeed: f32,
+    reactionDeposit: f32,
+    reactionSensing: f32,
+    showReaction: u32,
+
+    surface: u32,
+
+    displayMode: u32,
+    densityScale: f32,
+    hdrIntensity: f32,
+
+    rescale: vec2<f32>,
+
+    seed: u32,
+    statsThreshold: f32,
 // #ifdef SIXTEEN_BYTE_ALIGNMENT
 //     _padding: vec3<f32>,
 // #endif
@@ -35,6 +173,8 @@ struct SettingsUniform {
 
 const TAU: f32 = 6.283185;
 const PI: f32 = 3.1415927;
+// Fixed point scale of the deposit buffer.
+const DEPOSIT_SCALE: f32 = 65536.0;
 
 fn hash(value: u32) -> u32 {
     var state = value;
@@ -56,10 +196,134 @@ fn scale01(value: u32) -> f32 {
 }
 
 
+const MAP_MOVE_SPEED: u32 = 1u;
+const MAP_TURN_SPEED: u32 = 2u;
+const MAP_SENSOR_OFFSET: u32 = 3u;
+const MAP_TRAIL_WEIGHT: u32 = 4u;
+const MAP_DECAY_RATE: u32 = 5u;
+const MAP_DIFFUSE_RATE: u32 = 6u;
+
+// Scale of a parameter at a position in the trail map, from the parameter maps targeting it.
+fn parameterScale(parameter: u32, position: vec2<f32>) -> f32 {
+    let uv = position / vec2<f32>(f32(settings.dimX), f32(settings.dimY));
+
+    var scale = 1.0;
+    if (settings.parameterMapTargets.x == parameter) {
+        scale *= mix(1.0, textureSampleLevel(parameterMap0, parameterMapSampler, uv, 0.0).x, settings.parameterMapStrengths.x);
+    }
+    if (settings.parameterMapTargets.y == parameter) {
+        scale *= mix(1.0, textureSampleLevel(parameterMap1, parameterMapSampler, uv, 0.0).x, settings.parameterMapStrengths.y);
+    }
+    return max(0.0, scale);
+}
+
+// Display color of a trail intensity, from the gradient lookup texture.
+fn gradientColor(value: f32) -> vec4<f32> {
+    let width = f32(textureDimensions(gradientMap).x);
+    let u = (saturate(value) * (width - 1.0) + 0.5) / width;
+    return textureSampleLevel(gradientMap, parameterMapSampler, vec2<f32>(u, 0.5), 0.0);
+}
+
+// Velocity of the wind at a position in the trail map, in texels per second.
+fn windAt(position: vec2<f32>) -> vec2<f32> {
+    var velocity = settings.wind;
+    if (settings.flowMapEnabled != 0u) {
+        let uv = position / vec2<f32>(f32(settings.dimX), f32(settings.dimY));
+        let flow = textureSampleLevel(flowMap, parameterMapSampler, uv, 0.0).xy * 2.0 - 1.0;
+        velocity += flow * settings.flowMapStrength;
+    }
+    return velocity;
+}
+
+
+// Agent passes are dispatched in rows of workgroups to stay under the dispatch size limit.
+fn agentIndex(id: vec3<u32>, numWorkgroups: vec3<u32>) -> u32 {
+    return id.y * numWorkgroups.x * 16u + id.x;
+}
+
+fn cellIndex(position: vec2<f32>) -> u32 {
+    let cell = clamp(vec2<i32>(position), vec2<i32>(0), vec2<i32>(settings.dimX - 1, settings.dimY - 1));
+    return u32(cell.y * settings.dimX + cell.x);
+}
+
+// Adds a fixed point deposit without wrapping around when many agents deposit into one cell in a step.
+// An add that wraps raises the cell to the maximum afterwards, so it ends up saturated whatever order the adds run in.
+fn addDeposit(cellIdx: u32, amount: f32) {
+    let fixed = u32(round(amount * DEPOSIT_SCALE));
+    let old = atomicAdd(&deposits[cellIdx], fixed);
+    if (old + fixed < old) {
+        atomicMax(&deposits[cellIdx], 0xffffffffu);
+    }
+}
+
+// Moves an agent between texels of the occupancy grid, failing if the destination already holds `limit` agents.
+fn moveToCell(oldPos: vec2<f32>, newPos: vec2<f32>, limit: u32) -> bool {
+    let oldCell = cellIndex(oldPos);
+    let newCell = cellIndex(newPos);
+    if (oldCell == newCell) {
+        return true;
+    }
+
+    let occupants = atomicAdd(&cells[newCell], 1u);
+    if (occupants >= limit) {
+        atomicSub(&cells[newCell], 1u);
+        return false;
+    }
+    atomicSub(&cells[oldCell], 1u);
+    return true;
+}
+
+// Agents past `numAgents` start out dead, leaving room for agents spawned later on.
+fn initAgent(agentIdx: u32, position: vec2<f32>, angle: f32) {
+    if (agentIdx >= settings.maxAgents) {
+        return;
+    }
+
+    if (agentIdx == 0u) {
+        atomicStore(&counters.count, settings.numAgents);
+        atomicStore(&counters.alive, settings.numAgents);
+        atomicStore(&counters.free, 0u);
+    }
+
+    if (agentIdx < settings.numAgents) {
+        atomicAdd(&cells[cellIndex(position)], 1u);
+        agents[agentIdx] = Agent(position, angle, 1u, 0.0, 0.0, vec2<f32>(0.0));
+    } else {
+        agents[agentIdx] = Agent(position, angle, 0u, 0.0, 0.0, vec2<f32>(0.0));
+    }
+}
+
+// Takes a slot from the free list, or from past the end of the used slots if the free list is empty.
+// Returns `maxAgents` if every slot is taken.
+fn allocateAgent() -> u32 {
+    let free = atomicSub(&counters.free, 1u);
+    if (free > 0u && free <= settings.maxAgents) {
+        return freeList[free - 1u];
+    }
+    atomicAdd(&counters.free, 1u);
+
+    let agentIdx = atomicAdd(&counters.count, 1u);
+    if (agentIdx >= settings.maxAgents) {
+        atomicSub(&counters.count, 1u);
+        return settings.maxAgents;
+    }
+    return agentIdx;
+}
+
+fn releaseAgent(agentIdx: u32) {
+    agents[agentIdx].alive = 0u;
+    atomicSub(&counters.alive, 1u);
+    atomicSub(&cells[cellIndex(agents[agentIdx].position)], 1u);
+
+    let free = atomicAdd(&counters.free, 1u);
+    freeList[free] = agentIdx;
+}
+
+
 @compute @workgroup_size(16, 1, 1)
 fn initAgentsInwardCircle(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let agentIdx: u32 = id.x;
-    let randomState: u32 = num_workgroups.x * id.x;
+    let agentIdx: u32 = agentIndex(id, num_workgroups);
+    let randomState: u32 = num_workgroups.x * agentIdx + settings.seed;
 
     let randomRadius = random(randomState) * f32(settings.dimY) * 0.4;
     let randomAngle = random(randomState * 2u) * TAU;
@@ -68,12 +332,12 @@ fn initAgentsInwardCircle(@builtin(global_invocation_id) id: vec3<u32>, @builtin
 
     storageBarrier();
 
-    agents[agentIdx] = vec3<f32>(randomPosition.x, randomPosition.y, randomAngle - PI);
+    initAgent(agentIdx, randomPosition, randomAngle - PI);
 }
 @compute @workgroup_size(16, 1, 1)
 fn initAgentsOutwardCircle(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let agentIdx: u32 = id.x;
-    let randomState: u32 = num_workgroups.x * id.x;
+    let agentIdx: u32 = agentIndex(id, num_workgroups);
+    let randomState: u32 = num_workgroups.x * agentIdx + settings.seed;
 
     let randomRadius = random(randomState) * f32(settings.dimY) * 0.3;
     let randomAngle = random(randomState * 2u) * TAU;
@@ -82,12 +346,12 @@ fn initAgentsOutwardCircle(@builtin(global_invocation_id) id: vec3<u32>, @builti
 
     storageBarrier();
 
-    agents[agentIdx] = vec3<f32>(randomPosition.x, randomPosition.y, randomAngle);
+    initAgent(agentIdx, randomPosition, randomAngle);
 }
 @compute @workgroup_size(16, 1, 1)
 fn initAgentsInwardRing(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let agentIdx: u32 = id.x;
-    let randomState: u32 = num_workgroups.x * id.x;
+    let agentIdx: u32 = agentIndex(id, num_workgroups);
+    let randomState: u32 = num_workgroups.x * agentIdx + settings.seed;
 
     let radius = f32(settings.dimY) * 0.4;
     let randomAngle = random(randomState * 2u) * TAU;
@@ -96,12 +360,12 @@ fn initAgentsInwardRing(@builtin(global_invocation_id) id: vec3<u32>, @builtin(n
 
     storageBarrier();
 
-    agents[agentIdx] = vec3<f32>(randomPosition.x, randomPosition.y, randomAngle - PI);
+    initAgent(agentIdx, randomPosition, randomAngle - PI);
 }
 @compute @workgroup_size(16, 1, 1)
 fn initAgentsOutwardRing(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let agentIdx: u32 = id.x;
-    let randomState: u32 = num_workgroups.x * id.x;
+    let agentIdx: u32 = agentIndex(id, num_workgroups);
+    let randomState: u32 = num_workgroups.x * agentIdx + settings.seed;
 
     let radius = f32(settings.dimY) * 0.4;
     let randomAngle = random(randomState * 2u) * TAU;
@@ -110,61 +374,270 @@ fn initAgentsOutwardRing(@builtin(global_invocation_id) id: vec3<u32>, @builtin(
 
     storageBarrier();
 
-    agents[agentIdx] = vec3<f32>(randomPosition.x, randomPosition.y, randomAngle);
+    initAgent(agentIdx, randomPosition, randomAngle);
 }
 @compute @workgroup_size(16, 1, 1)
 fn initAgentsPoint(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let agentIdx: u32 = id.x;
-    let randomState: u32 = num_workgroups.x * id.x;
+    let agentIdx: u32 = agentIndex(id, num_workgroups);
+    let randomState: u32 = num_workgroups.x * agentIdx + settings.seed;
 
     let randomAngle = random(randomState * 2u) * TAU;
     let position = vec2<f32>(f32(settings.dimX) / 2.0, f32(settings.dimY) / 2.0);
 
     storageBarrier();
 
-    agents[agentIdx] = vec3<f32>(position.x, position.y, randomAngle);
+    initAgent(agentIdx, position, randomAngle);
 }
 
 
-fn sense(agent: vec3<f32>, sensorAngleOffset: f32) -> f32 {
-    let sensorAngle = agent.z + sensorAngleOffset;
-    let sensorDir = vec2<f32>(cos(sensorAngle), sin(sensorAngle));
+const SENSOR_BLOCK: u32 = 0u;
+const SENSOR_CIRCULAR: u32 = 1u;
+const SENSOR_BILINEAR: u32 = 2u;
+const MAX_SENSORS: u32 = 9u;
+
+const STEERING_CLASSIC: u32 = 0u;
+const STEERING_WEIGHTED: u32 = 1u;
+const STEERING_GRADIENT: u32 = 2u;
+
+const SURFACE_PLANE: u32 = 0u;
+const SURFACE_SPHERE: u32 = 1u;
+
+// Keeps a texel location inside the trail map. On the sphere the trail map is equirectangular, so it wraps horizontally.
+fn texelAt(location: vec2<i32>) -> vec2<i32> {
+    var texel = location;
+    if (settings.surface == SURFACE_SPHERE) {
+        texel.x = (texel.x % settings.dimX + settings.dimX) % settings.dimX;
+    }
+    return clamp(texel, vec2<i32>(0), vec2<i32>(settings.dimX - 1, settings.dimY - 1));
+}
+
+fn trailAt(location: vec2<i32>) -> f32 {
+    return textureLoad(trailMap, texelAt(location)).x;
+}
+
+// A point on the unit sphere and a heading tangent to it.
+struct SpherePoint {
+    point: vec3<f32>,
+    heading: vec3<f32>,
+}
+
+// Radians of arc per texel. The equirectangular trail map is twice as wide as it is high, so this is the same along both axes.
+fn texelArc() -> f32 {
+    return PI / f32(settings.dimY);
+}
+
+fn sphereEast(lon: f32) -> vec3<f32> {
+    return vec3<f32>(-sin(lon), 0.0, cos(lon));
+}
+
+fn sphereNorth(lon: f32, lat: f32) -> vec3<f32> {
+    return vec3<f32>(-sin(lat) * cos(lon), cos(lat), -sin(lat) * sin(lon));
+}
+
+fn liftToSphere(position: vec2<f32>) -> vec3<f32> {
+    let lon = position.x * texelArc() - PI;
+    let lat = PI * 0.5 - position.y * texelArc();
+    return vec3<f32>(cos(lat) * cos(lon), sin(lat), cos(lat) * sin(lon));
+}
+
+fn projectToTexture(point: vec3<f32>) -> vec2<f32> {
+    let dims = vec2<f32>(f32(settings.dimX), f32(settings.dimY));
+    let lon = atan2(point.z, point.x);
+    let lat = asin(clamp(point.y, -1.0, 1.0));
+    let position = vec2<f32>((lon + PI) / texelArc(), (PI * 0.5 - lat) / texelArc());
+    return vec2<f32>(position.x - floor(position.x / dims.x) * dims.x, min(position.y, dims.y - 0.001));
+}
+
+// The tangent at a position on the sphere along a displacement in texels, in radians of arc. Texture y points south.
+fn sphereTangent(position: vec2<f32>, displacement: vec2<f32>) -> vec3<f32> {
+    let lon = position.x * texelArc() - PI;
+    let lat = PI * 0.5 - position.y * texelArc();
+    return (sphereEast(lon) * displacement.x - sphereNorth(lon, lat) * displacement.y) * texelArc();
+}
+
+// The heading angle in texture space of a tangent at a point on the sphere.
+fn tangentAngle(point: vec3<f32>, tangent: vec3<f32>) -> f32 {
+    let lon = atan2(point.z, point.x);
+    let lat = asin(clamp(point.y, -1.0, 1.0));
+    return atan2(-dot(tangent, sphereNorth(lon, lat)), dot(tangent, sphereEast(lon)));
+}
+
+// Follows the great circle along a tangent for its length in radians. The heading is rotated along with the point,
+// which transports it parallel to the sphere.
+fn followGeodesic(point: vec3<f32>, heading: vec3<f32>, tangent: vec3<f32>) -> SpherePoint {
+    let arc = length(tangent);
+    if (arc < 0.000001) {
+        return SpherePoint(point, heading);
+    }
+    let axis = normalize(cross(point, tangent));
+    let cosArc = cos(arc);
+    let sinArc = sin(arc);
+    let newPoint = point * cosArc + cross(axis, point) * sinArc + axis * dot(axis, point) * (1.0 - cosArc);
+    let newHeading = heading * cosArc + cross(axis, heading) * sinArc + axis * dot(axis, heading) * (1.0 - cosArc);
+    return SpherePoint(normalize(newPoint), newHeading);
+}
+
+// Moves a texel position by a displacement in texels, along a great circle on the sphere.
+fn displace(position: vec2<f32>, displacement: vec2<f32>) -> vec2<f32> {
+    if (settings.surface != SURFACE_SPHERE) {
+        return position + displacement;
+    }
+    let moved = followGeodesic(liftToSphere(position), vec3<f32>(0.0), sphereTangent(position, displacement));
+    return projectToTexture(moved.point);
+}
+
+// The trail as perceived by agents, including the substrate chemical when reaction-diffusion is enabled.
+fn sensedAt(location: vec2<i32>) -> f32 {
+    var value = trailAt(location);
+    if (settings.reactionDiffusion != 0u) {
+        let sample = texelAt(location);
+        value += settings.reactionSensing * reaction[sample.y * settings.dimX + sample.x].x;
+    }
+    return value;
+}
+
+fn sampleSensedBilinear(position: vec2<f32>) -> f32 {
+    let samplePos = position - 0.5;
+    let base = vec2<i32>(floor(samplePos));
+    let t = fract(samplePos);
+
+    let top = mix(sensedAt(base), sensedAt(base + vec2<i32>(1, 0)), t.x);
+    let bottom = mix(sensedAt(base + vec2<i32>(0, 1)), sensedAt(base + vec2<i32>(1, 1)), t.x);
+    return mix(top, bottom, t.y);
+}
+
+fn sampleTrailBilinear(position: vec2<f32>) -> f32 {
+    let samplePos = position - 0.5;
+    let base = vec2<i32>(floor(samplePos));
+    let t = fract(samplePos);
+
+    let top = mix(trailAt(base), trailAt(base + vec2<i32>(1, 0)), t.x);
+    let bottom = mix(trailAt(base + vec2<i32>(0, 1)), trailAt(base + vec2<i32>(1, 1)), t.x);
+    return mix(top, bottom, t.y);
+}
+
+// On the sphere a sensor reaches out along a great circle, and its footprint is laid out in the tangent plane there,
+// so it covers the same area at every latitude. Only the sample points are projected onto the trail map.
+fn senseOnSphere(position: vec2<f32>, sensorAngle: f32, sensorOffsetDst: f32) -> f32 {
+    let direction = vec2<f32>(cos(sensorAngle), sin(sensorAngle));
+    let sensor = followGeodesic(liftToSphere(position), vec3<f32>(0.0), sphereTangent(position, direction * sensorOffsetDst)).point;
+    let sensorPos = projectToTexture(sensor);
+    if (settings.sensorModel == SENSOR_BILINEAR) {
+        return sampleSensedBilinear(sensorPos);
+    }
+
+    let radiusSquared = settings.sensorSize * settings.sensorSize;
+
+    var sum = 0.0;
+    for (var offsetX = -settings.sensorSize; offsetX <= settings.sensorSize; offsetX++) {
+        for (var offsetY = -settings.sensorSize; offsetY <= settings.sensorSize; offsetY++) {
+            if (settings.sensorModel == SENSOR_CIRCULAR && offsetX * offsetX + offsetY * offsetY > radiusSquared) {
+                continue;
+            }
+            let samplePoint = normalize(sensor + sphereTangent(sensorPos, vec2<f32>(f32(offsetX), f32(offsetY))));
+            sum += sensedAt(vec2<i32>(projectToTexture(samplePoint)));
+        }
+    }
+    return sum;
+}
+
+fn sense(agent: Agent, sensorAngleOffset: f32) -> f32 {
+    let sensorAngle = agent.angle + sensorAngleOffset;
+
+    let sensorOffsetDst = settings.sensorOffsetDst * parameterScale(MAP_SENSOR_OFFSET, agent.position);
+    if (settings.surface == SURFACE_SPHERE) {
+        return senseOnSphere(agent.position, sensorAngle, sensorOffsetDst);
+    }
+
+    let sensorPos = agent.position + vec2<f32>(cos(sensorAngle), sin(sensorAngle)) * sensorOffsetDst;
+    if (settings.sensorModel == SENSOR_BILINEAR) {
+        return sampleSensedBilinear(sensorPos);
+    }
 
-    let sensorPos = agent.xy + sensorDir * settings.sensorOffsetDst;
     let sensorCenterX = i32(sensorPos.x);
     let sensorCenterY = i32(sensorPos.y);
+    let radiusSquared = settings.sensorSize * settings.sensorSize;
 
     var sum = 0.0;
     for (var offsetX = -settings.sensorSize; offsetX <= settings.sensorSize; offsetX++) {
         for (var offsetY = -settings.sensorSize; offsetY <= settings.sensorSize; offsetY++) {
-            let sampleX = min(settings.dimX - 1, max(0, sensorCenterX + offsetX));
-            let sampleY = min(settings.dimY - 1, max(0, sensorCenterY + offsetY));
-            sum += textureLoad(trailMap, vec2<i32>(sampleX, sampleY)).w;
+            if (settings.sensorModel == SENSOR_CIRCULAR && offsetX * offsetX + offsetY * offsetY > radiusSquared) {
+                continue;
+            }
+            sum += sensedAt(vec2<i32>(sensorCenterX + offsetX, sensorCenterY + offsetY));
         }
     }
     return sum;
 }
 
 @compute @workgroup_size(16, 1, 1)
-fn updateAgents(@builtin(global_invocation_id) id: vec3<u32>) {
+fn updateAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let agentIdx = agentIndex(id, num_workgroups);
+    if (agentIdx >= atomicLoad(&counters.count)) {
+        return;
+    }
 
-    let agent = agents[id.x];
-    let pos = vec2<f32>(agent.x, agent.y);
-    let angle = agent.z;
+    let agent = agents[agentIdx];
+    if (agent.alive == 0u) {
+        return;
+    }
 
-    var rng = hash(u32(i32(pos.y) * settings.dimX + i32(pos.x)) + hash(id.x + u32(settings.time) * 100000u));
+    let pos = agent.position;
+    let angle = agent.angle;
 
+    var rng = hash(u32(i32(pos.y) * settings.dimX + i32(pos.x)) + hash(agentIdx + u32(settings.time) * 100000u + settings.seed));
+
+    // Sensors are fanned out symmetrically around the heading, the ones past the center sensor are on the left.
     let sensorAngleRad = settings.sensorAngleSpacing * (PI / 180.0);
-    let weightForward = sense(agent, 0.0);
-    let weightLeft = sense(agent, sensorAngleRad);
-    let weightRight = sense(agent, -sensorAngleRad);
+    let sensorCount = clamp(settings.sensorCount, 1u, MAX_SENSORS);
+    let centerSensor = sensorCount / 2u;
+
+    var sensors: array<f32, 9>;
+    var weightForward = 0.0;
+    var weightLeft = 0.0;
+    var weightRight = 0.0;
+    for (var i = 0u; i < sensorCount; i++) {
+        let noise = (random(rng + i) * 2.0 - 1.0) * settings.sensorNoise;
+        sensors[i] = sense(agent, (f32(i) - f32(centerSensor)) * sensorAngleRad) * (1.0 + noise);
+
+        if (i == centerSensor) {
+            weightForward = sensors[i];
+        } else if (i > centerSensor) {
+            weightLeft = max(weightLeft, sensors[i]);
+        } else {
+            weightRight = max(weightRight, sensors[i]);
+        }
+    }
 
     let steerStrength = scale01(rng);
-    let turnSpeed = settings.turnSpeed * TAU;
+    let turnSpeed = settings.turnSpeed * parameterScale(MAP_TURN_SPEED, pos) * TAU;
 
     var newAngle = angle;
 
-    if weightForward > weightLeft && weightForward > weightRight {
+    if (settings.steeringModel == STEERING_WEIGHTED) {
+        // Turns towards the softmax weighted average of the sensor angles.
+        var maxWeight = 0.0;
+        for (var i = 0u; i < sensorCount; i++) {
+            maxWeight = max(maxWeight, sensors[i]);
+        }
+
+        var totalWeight = 0.0;
+        var targetOffset = 0.0;
+        for (var i = 0u; i < sensorCount; i++) {
+            let weight = exp((sensors[i] - maxWeight) / (settings.steeringTemperature * max(maxWeight, 0.00001)));
+            totalWeight += weight;
+            targetOffset += weight * (f32(i) - f32(centerSensor)) * sensorAngleRad;
+        }
+
+        let maxTurn = steerStrength * turnSpeed * settings.deltaTime;
+        newAngle += clamp(targetOffset / totalWeight, -maxTurn, maxTurn);
+    }
+    else if (settings.steeringModel == STEERING_GRADIENT) {
+        // Turns proportionally to the normalized difference between both sides.
+        let gradient = (weightLeft - weightRight) / max(weightLeft + weightRight, 0.00001);
+        newAngle += gradient * turnSpeed * settings.deltaTime;
+    }
+    else if weightForward > weightLeft && weightForward > weightRight {
         newAngle += 0.0;
     }
     else if weightForward < weightLeft && weightForward < weightRight {
@@ -177,28 +650,315 @@ fn updateAgents(@builtin(global_invocation_id) id: vec3<u32>) {
         newAngle += steerStrength * turnSpeed * settings.deltaTime;
     }
 
-    let direction = vec2<f32>(cos(angle), sin(angle));
-    var newPos = pos + direction * settings.deltaTime * settings.moveSpeed;
+    let moveAngle = select(angle, newAngle, settings.applyNewAngle != 0u);
+    let moveDirection = vec2<f32>(cos(moveAngle), sin(moveAngle));
+    let displacement = moveDirection * settings.deltaTime * settings.moveSpeed * parameterScale(MAP_MOVE_SPEED, pos) + windAt(pos) * settings.deltaTime;
+    var newPos = pos + displacement;
+    // On the sphere the geodesic step is as long as the displacement, whatever its projection onto the trail map.
+    var moved = displacement;
+    if (settings.surface == SURFACE_SPHERE) {
+        // The agent and its heading move together along a great circle in 3D, and are only projected back onto the trail map after.
+        let moved = followGeodesic(liftToSphere(pos), sphereTangent(pos, moveDirection), sphereTangent(pos, displacement));
+        newPos = projectToTexture(moved.point);
+        newAngle += tangentAngle(moved.point, moved.heading) - moveAngle;
+    }
+    var stuck = agent.stuck;
 
-    if (newPos.x < 0.0 || i32(newPos.x) >= settings.dimX || newPos.y < 0.0 || i32(newPos.y) >= settings.dimY) {
+    let inBounds = !(newPos.x < 0.0 || i32(newPos.x) >= settings.dimX || newPos.y < 0.0 || i32(newPos.y) >= settings.dimY);
+    if (!inBounds) {
         rng = hash(rng);
         let randAngle = scale01(rng) * TAU;
 
         newPos.x = min(f32(settings.dimX - 1), max(0.0, newPos.x));
         newPos.y = min(f32(settings.dimY - 1), max(0.0, newPos.y));
         newAngle = randAngle;
+        moved = newPos - pos;
+    }
+
+    var crowdLimit = select(0xffffffffu, settings.crowdLimit, settings.lifecycle != 0u);
+    if (settings.occupancyLimited != 0u) {
+        crowdLimit = 1u;
+    }
+
+    if (moveToCell(pos, newPos, crowdLimit)) {
+        stuck = 0.0;
+
+        if (inBounds) {
+            let trailWeight = settings.trailWeight * parameterScale(MAP_TRAIL_WEIGHT, newPos);
+            addDeposit(cellIndex(newPos), trailWeight * settings.deltaTime);
+        }
     } else {
-        let location = vec2<i32>(newPos);
-        let oldTrail = textureLoad(trailMap, location);
+        newPos = pos;
+        moved = vec2<f32>(0.0);
+        stuck += settings.deltaTime;
 
-        storageBarrier();
-        textureStore(trailMap, location, min(settings.colorA, oldTrail + settings.trailWeight * settings.deltaTime));
+        // Blocked agents turn to a random heading instead of piling up, as in the original Physarum model.
+        if (settings.occupancyLimited != 0u) {
+            rng = hash(rng);
+            newAngle = scale01(rng) * TAU;
+        }
     }
+
+    let age = agent.age + settings.deltaTime;
+
     storageBarrier();
-    agents[id.x] = vec3<f32>(newPos.x, newPos.y, newAngle);
+    agents[agentIdx] = Agent(newPos, newAngle, 1u, age, stuck, moved / settings.deltaTime);
+
+    if (settings.lifecycle != 0u && (stuck > settings.deathThreshold || (settings.lifetime > 0.0 && age > settings.lifetime))) {
+        releaseAgent(agentIdx);
+    }
+}
+
+
+// Moves agents along with a resized trail map, and counts them into the new occupancy grid.
+@compute @workgroup_size(16, 1, 1)
+fn rescaleAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let agentIdx = agentIndex(id, num_workgroups);
+    if (agentIdx >= settings.maxAgents) {
+        return;
+    }
+
+    let maxPosition = vec2<f32>(f32(settings.dimX), f32(settings.dimY)) - 0.001;
+    let position = min(agents[agentIdx].position * settings.rescale, maxPosition);
+    agents[agentIdx].position = position;
+    if (agents[agentIdx].alive != 0u) {
+        atomicAdd(&cells[cellIndex(position)], 1u);
+    }
 }
 
 
+// Sizes the indirect dispatch of the agent passes to the slots currently in use.
+@compute @workgroup_size(1, 1, 1)
+fn prepareDispatch() {
+    let count = min(atomicLoad(&counters.count), settings.maxAgents);
+    let workgroups = max(1u, (count + 15u) / 16u);
+    let width = min(workgroups, settings.agentsDispatchWidth);
+
+    dispatchArgs.x = width;
+    dispatchArgs.y = (workgroups + width - 1u) / width;
+    dispatchArgs.z = 1u;
+}
+
+@compute @workgroup_size(16, 1, 1)
+fn spawnAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let spawnIdx = agentIndex(id, num_workgroups);
+    if (spawnIdx >= settings.spawnCount) {
+        return;
+    }
+
+    let agentIdx = allocateAgent();
+    if (agentIdx >= settings.maxAgents) {
+        return;
+    }
+
+    let rng = hash(spawnIdx + hash(u32(settings.time * 1000.0) + settings.seed));
+    let radius = sqrt(scale01(rng)) * settings.brushRadius;
+    let offsetAngle = random(rng) * TAU;
+    let position = settings.brushPosition + vec2<f32>(cos(offsetAngle), sin(offsetAngle)) * radius;
+    let maxPosition = vec2<f32>(f32(settings.dimX - 1), f32(settings.dimY - 1));
+
+    let spawnPos = clamp(position, vec2<f32>(0.0), maxPosition);
+
+    atomicAdd(&cells[cellIndex(spawnPos)], 1u);
+    agents[agentIdx] = Agent(spawnPos, random(rng + 1u) * TAU, 1u, 0.0, 0.0, vec2<f32>(0.0));
+    atomicAdd(&counters.alive, 1u);
+}
+
+// Each living agent may give birth to a new agent in a free neighbouring texel with enough trail.
+@compute @workgroup_size(16, 1, 1)
+fn reproduceAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let parentIdx = agentIndex(id, num_workgroups);
+    if (parentIdx >= atomicLoad(&counters.count)) {
+        return;
+    }
+
+    let parent = agents[parentIdx];
+    if (parent.alive == 0u) {
+        return;
+    }
+
+    var rng = hash(parentIdx + hash(u32(settings.time * 1000.0) + settings.seed));
+    if (scale01(rng) > settings.birthRate * settings.deltaTime) {
+        return;
+    }
+
+    rng = hash(rng);
+    let offset = vec2<i32>(i32(rng % 3u) - 1, i32((rng / 3u) % 3u) - 1);
+    let cell = vec2<i32>(parent.position) + offset;
+    if (all(offset == vec2<i32>(0)) || cell.x < 0 || cell.y < 0 || cell.x >= settings.dimX || cell.y >= settings.dimY) {
+        return;
+    }
+    if (textureLoad(trailMap, cell).x < settings.birthThreshold) {
+        return;
+    }
+
+    let cellIdx = u32(cell.y * settings.dimX + cell.x);
+    if (atomicAdd(&cells[cellIdx], 1u) != 0u) {
+        atomicSub(&cells[cellIdx], 1u);
+        return;
+    }
+
+    let agentIdx = allocateAgent();
+    if (agentIdx >= settings.maxAgents) {
+        atomicSub(&cells[cellIdx], 1u);
+        return;
+    }
+
+    agents[agentIdx] = Agent(vec2<f32>(cell) + 0.5, random(rng) * TAU, 1u, 0.0, 0.0, vec2<f32>(0.0));
+    atomicAdd(&counters.alive, 1u);
+}
+
+@compute @workgroup_size(16, 1, 1)
+fn eraseAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let agentIdx = agentIndex(id, num_workgroups);
+    if (agentIdx >= atomicLoad(&counters.count)) {
+        return;
+    }
+
+    let agent = agents[agentIdx];
+    if (agent.alive == 0u) {
+        return;
+    }
+
+    if (distance(agent.position, settings.brushPosition) <= settings.brushRadius) {
+        releaseAgent(agentIdx);
+    }
+}
+
+
+// Adds the deposits of this step to the trail map. Deposits are summed with atomics first,
+// so every agent landing on the same texel is accounted for regardless of scheduling.
+@compute @workgroup_size(8, 8, 1)
+fn resolveDeposits(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+    let cellIdx = u32(location.y * settings.dimX + location.x);
+
+    let deposit = f32(atomicExchange(&deposits[cellIdx], 0u)) / DEPOSIT_SCALE;
+    if (deposit == 0.0) {
+        return;
+    }
+
+    if (settings.reactionDiffusion != 0u) {
+        let chemicals = reaction[cellIdx];
+        reaction[cellIdx] = vec2<f32>(chemicals.x, min(1.0, chemicals.y + deposit * settings.reactionDeposit));
+    }
+
+    let oldTrail = textureLoad(trailMap, location).x;
+
+    storageBarrier();
+    textureStore(trailMap, location, vec4<f32>(min(1.0, oldTrail + deposit), 0.0, 0.0, 0.0));
+}
+
+
+// Semi-Lagrangian advection of the trail map along the wind, traced back from each texel into the blur map.
+@compute @workgroup_size(8, 8, 1)
+fn advectTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+    let texelPos = vec2<f32>(location) + 0.5;
+
+    let value = sampleTrailBilinear(displace(texelPos, -windAt(texelPos) * settings.deltaTime));
+
+    storageBarrier();
+    textureStore(blurMap, location, vec4<f32>(value, 0.0, 0.0, 0.0));
+}
+
+// Copies the advected trail back into the trail map, ahead of diffusion.
+@compute @workgroup_size(8, 8, 1)
+fn applyAdvection(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+    let value = textureLoad(blurMap, location).x;
+
+    storageBarrier();
+    textureStore(trailMap, location, vec4<f32>(value, 0.0, 0.0, 0.0));
+}
+
+
+@compute @workgroup_size(8, 8, 1)
+fn initReaction(@builtin(global_invocation_id) id: vec3<u32>) {
+    let cellIdx = id.y * u32(settings.dimX) + id.x;
+    let texels = u32(settings.dimX * settings.dimY);
+
+    reaction[cellIdx] = vec2<f32>(1.0, 0.0);
+    reaction[texels + cellIdx] = vec2<f32>(1.0, 0.0);
+}
+
+fn reactionLaplacian(source: u32, location: vec2<i32>) -> vec2<f32> {
+    var sum = vec2<f32>(0.0);
+    for (var offsetX = -1; offsetX <= 1; offsetX++) {
+        for (var offsetY = -1; offsetY <= 1; offsetY++) {
+            if (offsetX == 0 && offsetY == 0) {
+                continue;
+            }
+            let sample = clamp(location + vec2<i32>(offsetX, offsetY), vec2<i32>(0), vec2<i32>(settings.dimX - 1, settings.dimY - 1));
+            let weight = select(0.05, 0.2, offsetX == 0 || offsetY == 0);
+            sum += reaction[source + u32(sample.y * settings.dimX + sample.x)] * weight;
+        }
+    }
+    return sum - reaction[source + u32(location.y * settings.dimX + location.x)];
+}
+
+// One Gray-Scott step from one half of the reaction buffer into the other.
+fn stepReaction(source: u32, destination: u32, location: vec2<i32>) {
+    let cellIdx = u32(location.y * settings.dimX + location.x);
+    let chemicals = reaction[source + cellIdx];
+    let laplacian = reactionLaplacian(source, location);
+
+    let reactionRate = chemicals.x * chemicals.y * chemicals.y;
+    let du = settings.diffusionRateU * laplacian.x - reactionRate + settings.feedRate * (1.0 - chemicals.x);
+    let dv = settings.diffusionRateV * laplacian.y + reactionRate - (settings.feedRate + settings.killRate) * chemicals.y;
+
+    reaction[destination + cellIdx] = saturate(chemicals + vec2<f32>(du, dv) * settings.reactionSpeed);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn reactForward(@builtin(global_invocation_id) id: vec3<u32>) {
+    stepReaction(0u, u32(settings.dimX * settings.dimY), vec2<i32>(i32(id.x), i32(id.y)));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn reactBackward(@builtin(global_invocation_id) id: vec3<u32>) {
+    stepReaction(u32(settings.dimX * settings.dimY), 0u, vec2<i32>(i32(id.x), i32(id.y)));
+}
+
+
+const KERNEL_BOX: u32 = 0u;
+const KERNEL_GAUSSIAN: u32 = 1u;
+const KERNEL_ANISOTROPIC: u32 = 2u;
+
+fn kernelWeight(offset: i32, sigma: f32) -> f32 {
+    if (settings.diffusionKernel == KERNEL_BOX) {
+        return 1.0;
+    }
+    return exp(-f32(offset * offset) / (2.0 * sigma * sigma));
+}
+
+// The anisotropic kernel stretches the vertical falloff of the gaussian kernel.
+fn verticalSigma() -> f32 {
+    if (settings.diffusionKernel == KERNEL_ANISOTROPIC) {
+        return settings.diffusionSigma * settings.diffusionAnisotropy;
+    }
+    return settings.diffusionSigma;
+}
+
+// First half of the separable diffusion blur, from the trail map into the blur map.
+@compute @workgroup_size(8, 8, 1)
+fn blurTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+
+    var sum = 0.0;
+    var totalWeight = 0.0;
+
+    for (var offsetX = -settings.diffusionRadius; offsetX <= settings.diffusionRadius; offsetX++) {
+        let weight = kernelWeight(offsetX, settings.diffusionSigma);
+        sum += trailAt(vec2<i32>(location.x + offsetX, location.y)) * weight;
+        totalWeight += weight;
+    }
+
+    storageBarrier();
+    textureStore(blurMap, location, vec4<f32>(sum / totalWeight, 0.0, 0.0, 0.0));
+}
+
+// Second half of the separable diffusion blur, from the blur map back into the trail map.
 @compute @workgroup_size(8, 8, 1)
 fn updateTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
     let location = vec2<i32>(i32(id.x), i32(id.y));
@@ -208,22 +968,192 @@ fn updateTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
     // }
 
     var sum = 0.0;
-    let oldColor = textureLoad(trailMap, location).w;
+    var totalWeight = 0.0;
+    let oldColor = textureLoad(trailMap, location).x;
+    let sigma = verticalSigma();
 
-    for (var offsetX = -1; offsetX <= 1; offsetX++) {
-        for (var offsetY = -1; offsetY <= 1; offsetY++) {
-            let sampleX = min(settings.dimX - 1, max(0, location.x + offsetX));
-            let sampleY = min(settings.dimY - 1, max(0, location.y + offsetY));
-            sum += textureLoad(trailMap, vec2<i32>(sampleX, sampleY)).w;
-        }
+    for (var offsetY = -settings.diffusionRadius; offsetY <= settings.diffusionRadius; offsetY++) {
+        let sampleY = min(settings.dimY - 1, max(0, location.y + offsetY));
+        let weight = kernelWeight(offsetY, sigma);
+        sum += textureLoad(blurMap, vec2<i32>(location.x, sampleY)).x * weight;
+        totalWeight += weight;
     }
 
-    let blurred = sum / 9.0;
-    let diffuseWeight = saturate(settings.diffuseRate * settings.deltaTime);
+    let texelPos = vec2<f32>(location) + 0.5;
+    let blurred = sum / totalWeight;
+    let diffuseWeight = saturate(settings.diffuseRate * parameterScale(MAP_DIFFUSE_RATE, texelPos) * settings.deltaTime);
     let finalBlurred = oldColor * (1.0 - diffuseWeight) + blurred * diffuseWeight;
-    let finalValue = finalBlurred - settings.decayRate * settings.deltaTime;
-    let finalCol = settings.colorB + (settings.colorA - settings.colorB) * max(0.0, min(1.0, finalValue));
+    let finalValue = finalBlurred - settings.decayRate * parameterScale(MAP_DECAY_RATE, texelPos) * settings.deltaTime;
 
     storageBarrier();
-    textureStore(trailMap, location, vec4<f32>(finalCol.xyz, max(0.0, finalValue)));
-}
\ No newline at end of file
+    textureStore(trailMap, location, vec4<f32>(max(0.0, finalValue), 0.0, 0.0, 0.0));
+}
+
+
+@compute @workgroup_size(8, 8, 1)
+fn paintTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+
+    let dst = distance(vec2<f32>(location) + 0.5, settings.brushPosition);
+    if (dst > settings.brushRadius) {
+        return;
+    }
+
+    let falloff = 1.0 - dst / settings.brushRadius;
+    let oldValue = textureLoad(trailMap, location).x;
+    let finalValue = saturate(oldValue + settings.brushStrength * falloff * settings.deltaTime);
+
+    storageBarrier();
+    textureStore(trailMap, location, vec4<f32>(finalValue, 0.0, 0.0, 0.0));
+}
+
+
+const DISPLAY_TRAIL: u32 = 0u;
+const DISPLAY_RAW_TRAIL: u32 = 1u;
+const DISPLAY_AGENT_DENSITY: u32 = 2u;
+const DISPLAY_HEADING: u32 = 3u;
+const DISPLAY_SENSOR_DISAGREEMENT: u32 = 4u;
+// Fixed point scale of the agent splats.
+const SPLAT_SCALE: f32 = 1024.0;
+
+// Spread between the strongest and weakest sensor of an agent, relative to the strongest.
+fn sensorDisagreement(agent: Agent) -> f32 {
+    let sensorAngleRad = settings.sensorAngleSpacing * (PI / 180.0);
+    let sensorCount = clamp(settings.sensorCount, 1u, MAX_SENSORS);
+    let centerSensor = sensorCount / 2u;
+
+    var minWeight = 3.402823e38;
+    var maxWeight = 0.0;
+    for (var i = 0u; i < sensorCount; i++) {
+        let weight = max(0.0, sense(agent, (f32(i) - f32(centerSensor)) * sensorAngleRad));
+        minWeight = min(minWeight, weight);
+        maxWeight = max(maxWeight, weight);
+    }
+    return (maxWeight - minWeight) / max(maxWeight, 0.00001);
+}
+
+// Adds each living agent to the splats of its texel. Only dispatched for the display modes that need it.
+@compute @workgroup_size(16, 1, 1)
+fn splatAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let agentIdx = agentIndex(id, num_workgroups);
+    if (agentIdx >= atomicLoad(&counters.count)) {
+        return;
+    }
+
+    let agent = agents[agentIdx];
+    if (agent.alive == 0u) {
+        return;
+    }
+
+    let splatIdx = cellIndex(agent.position) * 3u;
+    if (settings.displayMode == DISPLAY_HEADING) {
+        atomicAdd(&agentSplats[splatIdx], i32(round(cos(agent.angle) * SPLAT_SCALE)));
+        atomicAdd(&agentSplats[splatIdx + 1u], i32(round(sin(agent.angle) * SPLAT_SCALE)));
+    } else if (settings.displayMode == DISPLAY_SENSOR_DISAGREEMENT) {
+        atomicAdd(&agentSplats[splatIdx + 2u], i32(round(sensorDisagreement(agent) * SPLAT_SCALE)));
+    }
+}
+
+fn hueColor(hue: f32) -> vec3<f32> {
+    return saturate(abs(fract(hue + vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0);
+}
+
+// Colors the trail map, or one of the agent views, for display. Exposure, tone mapping and bloom are applied afterwards by the camera.
+@compute @workgroup_size(8, 8, 1)
+fn displayTrailmap(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+    let cellIdx = u32(location.y * settings.dimX + location.x);
+    let agentCount = f32(atomicLoad(&cells[cellIdx]));
+
+    var color = vec3<f32>(0.0);
+    if (settings.displayMode == DISPLAY_AGENT_DENSITY) {
+        color = gradientColor(agentCount / settings.densityScale).xyz;
+    } else if (settings.displayMode == DISPLAY_HEADING) {
+        if (agentCount > 0.0) {
+            let heading = vec2<f32>(f32(atomicLoad(&agentSplats[cellIdx * 3u])), f32(atomicLoad(&agentSplats[cellIdx * 3u + 1u]))) / (SPLAT_SCALE * agentCount);
+            // Hue shows the mean heading and brightness how well the agents in the texel agree on it.
+            color = hueColor(atan2(heading.y, heading.x) / TAU + 0.5) * min(1.0, length(heading));
+        }
+    } else if (settings.displayMode == DISPLAY_SENSOR_DISAGREEMENT) {
+        if (agentCount > 0.0) {
+            color = gradientColor(f32(atomicLoad(&agentSplats[cellIdx * 3u + 2u])) / (SPLAT_SCALE * agentCount)).xyz;
+        }
+    } else {
+        var value = textureLoad(trailMap, location).x;
+        if (settings.showReaction != 0u) {
+            value = reaction[cellIdx].y;
+        }
+
+        if (settings.displayMode == DISPLAY_RAW_TRAIL) {
+            color = vec3<f32>(value);
+        } else {
+            color = gradientColor(value).xyz;
+        }
+    }
+
+    // Scaled past one, bright trails reach exposure, tone mapping and bloom as high dynamic range values.
+    textureStore(displayMap, location, vec4<f32>(color * settings.hdrIntensity, 1.0));
+}
+
+// Fixed point scale of the statistics sums.
+const STATS_SCALE: f32 = 256.0;
+const STATS_TRAIL_SUM: u32 = 0u;
+const STATS_SPEED_SUM: u32 = 2u;
+const HEADING_BINS: u32 = 16u;
+
+// Adds to one of the fixed point sums, carrying into its high word when the low word wraps around.
+fn addToSum(sumIdx: u32, value: f32) {
+    let fixed = u32(round(value * STATS_SCALE));
+    let old = atomicAdd(&stats.sums[sumIdx], fixed);
+    if (old + fixed < old) {
+        atomicAdd(&stats.sums[sumIdx + 1u], 1u);
+    }
+}
+
+// Sum, maximum and covered count of the texels in a workgroup.
+var<workgroup> trailTotals: array<vec3<f32>, 64>;
+
+// Reduces the trail map within each workgroup, then adds the workgroup totals to the statistics.
+@compute @workgroup_size(8, 8, 1)
+fn reduceTrail(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) localIdx: u32) {
+    let value = max(textureLoad(trailMap, vec2<i32>(id.xy)).x, 0.0);
+    trailTotals[localIdx] = vec3<f32>(value, value, f32(value > settings.statsThreshold));
+    workgroupBarrier();
+
+    for (var stride = 32u; stride > 0u; stride = stride / 2u) {
+        if (localIdx < stride) {
+            let a = trailTotals[localIdx];
+            let b = trailTotals[localIdx + stride];
+            trailTotals[localIdx] = vec3<f32>(a.x + b.x, max(a.y, b.y), a.z + b.z);
+        }
+        workgroupBarrier();
+    }
+
+    if (localIdx == 0u) {
+        let totals = trailTotals[0];
+        addToSum(STATS_TRAIL_SUM, totals.x);
+        // The bits of non-negative floats order the same way as their values.
+        atomicMax(&stats.trailMax, bitcast<u32>(totals.y));
+        atomicAdd(&stats.covered, u32(totals.z));
+    }
+}
+
+// Adds the speed and heading of each living agent to the statistics.
+@compute @workgroup_size(16, 1, 1)
+fn reduceAgents(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let agentIdx = agentIndex(id, num_workgroups);
+    if (agentIdx >= atomicLoad(&counters.count)) {
+        return;
+    }
+
+    let agent = agents[agentIdx];
+    if (agent.alive == 0u) {
+        return;
+    }
+
+    addToSum(STATS_SPEED_SUM, length(agent.velocity));
+    atomicAdd(&stats.agents, 1u);
+
+    let bin = min(u32(fract(agent.angle / TAU) * f32(HEADING_BINS)), HEADING_BINS - 1u);
+    atomicAdd(&stats.headings[bin], 1u);
+}
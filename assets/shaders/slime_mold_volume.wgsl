@@ -0,0 +1,271 @@
+@group(0) @binding(0)
+var volume: texture_storage_3d<r32float, read_write>;
+
+@group(0) @binding(1)
+var volumeTemp: texture_storage_3d<r32float, read_write>;
+
+@group(0) @binding(2)
+var<storage, read_write> deposits: array<atomic<u32>>;
+
+@group(0) @binding(3)
+var display: texture_storage_2d<rgba8unorm, write>;
+
+@group(1) @binding(0)
+var<storage, read_write> agents: array<VolumeAgent>;
+
+@group(2) @binding(0)
+var<uniform> settings: VolumeSettingsUniform;
+
+@group(2) @binding(1)
+var gradientMap: texture_2d<f32>;
+
+@group(2) @binding(2)
+var gradientSampler: sampler;
+
+
+struct VolumeAgent {
+    position: vec3<f32>,
+    // Rotation of the sensor ring around the heading.
+    roll: f32,
+    direction: vec3<f32>,
+    _padding: f32,
+}
+
+struct VolumeSettingsUniform {
+    size: i32,
+    numAgents: u32,
+    deltaTime: f32,
+    time: f32,
+
+    moveSpeed: f32,
+    turnSpeed: f32,
+    trailWeight: f32,
+    decayRate: f32,
+    diffuseRate: f32,
+    sensorAngleSpacing: f32,
+    sensorOffsetDst: f32,
+    view: u32,
+
+    slice: f32,
+    viewAngle: f32,
+    density: f32,
+}
+
+
+const TAU: f32 = 6.283185;
+const PI: f32 = 3.1415927;
+// Fixed point scale of the deposit buffer.
+const DEPOSIT_SCALE: f32 = 65536.0;
+
+const VIEW_PROJECTION: u32 = 0u;
+const VIEW_SLICE: u32 = 1u;
+const RAYMARCH_STEPS: i32 = 192;
+
+fn hash(value: u32) -> u32 {
+    var state = value;
+    state = state ^ 2747636419u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    return state;
+}
+
+fn scale01(value: u32) -> f32 {
+    return f32(value) / 4294967295.0;
+}
+
+fn randomDirection(seed: u32) -> vec3<f32> {
+    let z = scale01(hash(seed)) * 2.0 - 1.0;
+    let phi = scale01(hash(seed + 1u)) * TAU;
+    let r = sqrt(1.0 - z * z);
+    return vec3<f32>(r * cos(phi), r * sin(phi), z);
+}
+
+// Display color of a trail intensity, from the gradient lookup texture.
+fn gradientColor(value: f32) -> vec4<f32> {
+    let width = f32(textureDimensions(gradientMap).x);
+    let u = (saturate(value) * (width - 1.0) + 0.5) / width;
+    return textureSampleLevel(gradientMap, gradientSampler, vec2<f32>(u, 0.5), 0.0);
+}
+
+fn voxelIndex(position: vec3<f32>) -> u32 {
+    let voxel = clamp(vec3<i32>(position), vec3<i32>(0), vec3<i32>(settings.size - 1));
+    return u32((voxel.z * settings.size + voxel.y) * settings.size + voxel.x);
+}
+
+// Adds a fixed point deposit without wrapping around when many agents deposit into one voxel in a step.
+// An add that wraps raises the voxel to the maximum afterwards, so it ends up saturated whatever order the adds run in.
+fn addDeposit(voxelIdx: u32, amount: f32) {
+    let fixed = u32(round(amount * DEPOSIT_SCALE));
+    let old = atomicAdd(&deposits[voxelIdx], fixed);
+    if (old + fixed < old) {
+        atomicMax(&deposits[voxelIdx], 0xffffffffu);
+    }
+}
+
+fn trailAt(location: vec3<i32>) -> f32 {
+    return textureLoad(volume, clamp(location, vec3<i32>(0), vec3<i32>(settings.size - 1))).x;
+}
+
+
+@compute @workgroup_size(16, 1, 1)
+fn initVolumeAgents(@builtin(global_invocation_id) id: vec3<u32>) {
+    let agentIdx = id.x;
+    if (agentIdx >= settings.numAgents) {
+        return;
+    }
+
+    let center = vec3<f32>(f32(settings.size) * 0.5);
+    let radius = f32(settings.size) * 0.35 * pow(scale01(hash(agentIdx * 3u + 7u)), 1.0 / 3.0);
+    let position = center + randomDirection(agentIdx * 3u) * radius;
+
+    agents[agentIdx] = VolumeAgent(position, 0.0, randomDirection(agentIdx * 5u + 11u), 0.0);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn initVolume(@builtin(global_invocation_id) id: vec3<u32>) {
+    textureStore(volume, vec3<i32>(id), vec4<f32>(0.0));
+    textureStore(volumeTemp, vec3<i32>(id), vec4<f32>(0.0));
+}
+
+fn sense(position: vec3<f32>, direction: vec3<f32>) -> f32 {
+    return trailAt(vec3<i32>(position + direction * settings.sensorOffsetDst));
+}
+
+@compute @workgroup_size(16, 1, 1)
+fn updateVolumeAgents(@builtin(global_invocation_id) id: vec3<u32>) {
+    let agentIdx = id.x;
+    if (agentIdx >= settings.numAgents) {
+        return;
+    }
+
+    let agent = agents[agentIdx];
+    let pos = agent.position;
+    let forward = agent.direction;
+    var rng = hash(u32(pos.y * f32(settings.size) + pos.x) * 1973u + hash(agentIdx + u32(settings.time * 100000.0)));
+
+    // An orthonormal basis around the heading, rotated by the roll of the agent.
+    let helper = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(forward.y) > 0.99);
+    let side = normalize(cross(forward, helper));
+    let up = cross(side, forward);
+    let right = side * cos(agent.roll) + up * sin(agent.roll);
+    let top = cross(right, forward);
+
+    // A forward sensor and a ring of four sensors tilted away from the heading by the sensor angle.
+    let sensorAngle = settings.sensorAngleSpacing * (PI / 180.0);
+    var bestDir = forward;
+    var bestWeight = sense(pos, forward);
+    for (var i = 0; i < 4; i++) {
+        let phi = f32(i) * (PI * 0.5);
+        let dir = forward * cos(sensorAngle) + (right * cos(phi) + top * sin(phi)) * sin(sensorAngle);
+        let weight = sense(pos, dir);
+        if (weight > bestWeight) {
+            bestWeight = weight;
+            bestDir = dir;
+        }
+    }
+
+    rng = hash(rng);
+    let steerStrength = scale01(rng);
+    let turn = min(1.0, steerStrength * settings.turnSpeed * settings.deltaTime / max(sensorAngle, 0.0001));
+    var newDir = normalize(mix(forward, bestDir, turn));
+
+    rng = hash(rng);
+    let roll = agent.roll + (scale01(rng) - 0.5) * TAU * 0.25;
+
+    var newPos = pos + newDir * settings.moveSpeed * settings.deltaTime;
+    let bound = f32(settings.size) - 0.001;
+    if (any(newPos < vec3<f32>(0.0)) || any(newPos >= vec3<f32>(bound))) {
+        newPos = clamp(newPos, vec3<f32>(0.0), vec3<f32>(bound));
+        newDir = randomDirection(rng);
+    } else {
+        addDeposit(voxelIndex(newPos), settings.trailWeight * settings.deltaTime);
+    }
+
+    agents[agentIdx] = VolumeAgent(newPos, roll, newDir, 0.0);
+}
+
+// Adds the deposits of this step, then diffuses and decays the volume into the temporary volume.
+@compute @workgroup_size(4, 4, 4)
+fn diffuseVolume(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec3<i32>(id);
+
+    var sum = 0.0;
+    for (var offsetX = -1; offsetX <= 1; offsetX++) {
+        for (var offsetY = -1; offsetY <= 1; offsetY++) {
+            for (var offsetZ = -1; offsetZ <= 1; offsetZ++) {
+                sum += trailAt(location + vec3<i32>(offsetX, offsetY, offsetZ));
+            }
+        }
+    }
+
+    let voxelIdx = u32((location.z * settings.size + location.y) * settings.size + location.x);
+    let deposit = f32(atomicExchange(&deposits[voxelIdx], 0u)) / DEPOSIT_SCALE;
+
+    let oldValue = textureLoad(volume, location).x;
+    let blurred = sum / 27.0;
+    let diffuseWeight = saturate(settings.diffuseRate * settings.deltaTime);
+    let diffused = oldValue * (1.0 - diffuseWeight) + blurred * diffuseWeight;
+    let finalValue = clamp(diffused + deposit - settings.decayRate * settings.deltaTime, 0.0, 1.0);
+
+    textureStore(volumeTemp, location, vec4<f32>(finalValue, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn applyVolume(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec3<i32>(id);
+    textureStore(volume, location, textureLoad(volumeTemp, location));
+}
+
+
+// Draws a slice through the volume, or an emission-absorption raymarch of the volume seen from an orbiting camera.
+@compute @workgroup_size(8, 8, 1)
+fn renderVolume(@builtin(global_invocation_id) id: vec3<u32>) {
+    let location = vec2<i32>(i32(id.x), i32(id.y));
+    let displaySize = vec2<f32>(textureDimensions(display));
+    // Screen coordinates with the volume fitted to the display height.
+    let screen = (vec2<f32>(location) + 0.5 - displaySize * 0.5) / displaySize.y;
+
+    var intensity = 0.0;
+    if (settings.view == VIEW_SLICE) {
+        let uv = screen + 0.5;
+        if (all(uv >= vec2<f32>(0.0)) && all(uv < vec2<f32>(1.0))) {
+            let voxel = vec3<f32>(uv, settings.slice) * f32(settings.size);
+            intensity = trailAt(vec3<i32>(voxel));
+        }
+    } else {
+        let cosAngle = cos(settings.viewAngle);
+        let sinAngle = sin(settings.viewAngle);
+        let rotate = mat3x3<f32>(
+            vec3<f32>(cosAngle, 0.0, sinAngle),
+            vec3<f32>(0.0, 1.0, 0.0),
+            vec3<f32>(-sinAngle, 0.0, cosAngle),
+        );
+
+        let extent = 1.8;
+        let origin = rotate * vec3<f32>(screen.x * extent, -screen.y * extent, -0.9);
+        let direction = rotate * vec3<f32>(0.0, 0.0, 1.0);
+        let stepLength = extent / f32(RAYMARCH_STEPS);
+
+        var transmittance = 1.0;
+        for (var i = 0; i < RAYMARCH_STEPS; i++) {
+            let point = origin + direction * (f32(i) + 0.5) * stepLength;
+            if (any(abs(point) >= vec3<f32>(0.5))) {
+                continue;
+            }
+
+            let value = trailAt(vec3<i32>((point + 0.5) * f32(settings.size)));
+            let absorption = 1.0 - exp(-value * settings.density * stepLength * f32(settings.size));
+            intensity += transmittance * absorption;
+            transmittance *= 1.0 - absorption;
+            if (transmittance < 0.01) {
+                break;
+            }
+        }
+    }
+
+    let color = gradientColor(intensity);
+    textureStore(display, location, vec4<f32>(color.xyz, 1.0));
+}
@@ -0,0 +1,74 @@
+use bevy::{prelude::*, window::PrimaryWindow, render::extract_resource::ExtractResource};
+use bevy_egui::EguiContexts;
+
+use super::{texture::{SlimeMoldSprite, SimulationSize, cursor_to_texture}, ui::{UISettings, SimulationMode}};
+
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BrushTool {
+    /// Deposits (left) or removes (right) attractant in the trail map.
+    #[default]
+    Trail,
+    /// Spawns (left) or erases (right) agents.
+    Agents,
+}
+
+/// The brush stroke for the current frame, in texture coordinates.
+/// A positive strength is the left button action of the tool, a negative strength the right button one.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct BrushInput {
+    pub position: Vec2,
+    pub strength: f32,
+    pub tool: BrushTool,
+    pub spawn_count: u32,
+}
+
+impl BrushInput {
+    pub fn active(&self) -> bool {
+        self.strength != 0.0
+    }
+}
+
+pub fn update_brush(
+    mut brush: ResMut<BrushInput>,
+    mut contexts: EguiContexts,
+    mouse: Res<Input<MouseButton>>,
+    settings: Res<UISettings>,
+    simulation_size: Res<SimulationSize>,
+    time: Res<Time>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    sprite_query: Query<(&Sprite, &GlobalTransform), With<SlimeMoldSprite>>,
+) {
+    brush.strength = 0.0;
+    brush.spawn_count = 0;
+    if settings.mode != SimulationMode::Flat { return; }
+
+    let ctx = contexts.ctx_mut();
+    if ctx.wants_pointer_input() || ctx.is_pointer_over_area() { return; }
+
+    let sign = if mouse.pressed(MouseButton::Left) {
+        1.0
+    } else if mouse.pressed(MouseButton::Right) {
+        -1.0
+    } else {
+        return;
+    };
+
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok((sprite, sprite_transform)) = sprite_query.get_single() else { return };
+
+    if let Some(position) = cursor_to_texture(cursor, camera, camera_transform, sprite, sprite_transform, simulation_size.as_vec2()) {
+        brush.position = position;
+        brush.strength = match settings.brush_tool {
+            BrushTool::Trail => sign * settings.brush_strength,
+            BrushTool::Agents => sign,
+        };
+        brush.tool = settings.brush_tool;
+        if brush.tool == BrushTool::Agents && sign > 0.0 {
+            brush.spawn_count = (settings.spawn_rate * time.delta_seconds()).ceil() as u32;
+        }
+    }
+}
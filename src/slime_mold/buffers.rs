@@ -1,51 +1,249 @@
-use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{ShaderType, Buffer, UniformBuffer, BufferDescriptor, BufferUsages, BufferInitDescriptor}, Extract, renderer::{RenderDevice, RenderQueue}}};
+use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{ShaderType, Buffer, BindingResource, UniformBuffer, BufferDescriptor, BufferUsages, Texture, TextureView, TextureDescriptor, TextureViewDescriptor, Extent3d, TextureDimension, TextureFormat, TextureUsages}, Extract, renderer::{RenderDevice, RenderQueue}, render_asset::RenderAssets}};
+use bytemuck::{Pod, Zeroable};
 
-use super::{NUM_AGENTS, TEXTURE_SIZE, ui::UISettings};
+use super::{NUM_AGENTS, MAX_AGENTS, AGENTS_DISPATCH_WIDTH, texture::SimulationSize, ui::{UISettings, SimulationMode}, brush::BrushInput, readback::ReadbackBuffer, parameter_maps::{ParameterMapImages, MapTarget, FlowMapImage}};
 
 
-#[derive(Clone, Resource, ExtractResource, Reflect, ShaderType)]
-#[reflect(Resource)]
-struct AgentsArray {
-    arr: [[f32; 3]; NUM_AGENTS as usize]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct Agent {
+    pub position: [f32; 2],
+    pub angle: f32,
+    pub alive: u32,
+    pub age: f32,
+    /// Time spent unable to move into a crowded cell.
+    pub stuck: f32,
+    /// Distance per second covered in the last step, in texels along the axes of the trail map, or east and south on the sphere.
+    pub velocity: [f32; 2],
 }
 
-impl Default for AgentsArray {
-    fn default() -> Self {
-        Self { arr: [[0.0; 3]; NUM_AGENTS as usize] }
-    }
+/// Bookkeeping for the agent slots, shared by all agent passes on the GPU.
+/// `count` is the number of slots in use (alive or dead), dead slots below it are kept in the free list.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct AgentCounters {
+    pub count: u32,
+    pub alive: u32,
+    pub free: u32,
+    _padding: u32,
+}
+
+pub const HEADING_BINS: usize = 16;
+
+/// Totals of the statistics reductions, see `SimulationStats` in the shader.
+/// The trail and speed sums are fixed point, each split into a low and a high word.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct StatisticsSums {
+    pub trail_sum: [u32; 2],
+    pub speed_sum: [u32; 2],
+    /// Bits of the largest trail intensity.
+    pub trail_max: u32,
+    pub covered: u32,
+    pub agents: u32,
+    _padding: u32,
+    pub headings: [u32; HEADING_BINS],
 }
 
 #[derive(Resource)]
 pub struct SlimeMoldAgentsBuffer {
     pub storage: Buffer,
-    pub staging: Buffer,
+    pub counters: Buffer,
+    pub counters_readback: ReadbackBuffer,
+    pub free_list: Buffer,
+    /// Number of agents in each texel of the trail map.
+    pub cells: Buffer,
+    pub dispatch: Buffer,
+    /// Totals of the statistics reductions, cleared before each one.
+    pub stats: Buffer,
+    pub stats_readback: ReadbackBuffer,
     pub size: u64,
 }
 
 impl FromWorld for SlimeMoldAgentsBuffer {
     fn from_world(world: &mut World) -> Self {
         let device = world.resource::<RenderDevice>();
-        let size = (NUM_AGENTS * 3 * std::mem::size_of::<f32>() as u32) as u64;
-        
-        let staging = device.create_buffer(&BufferDescriptor {
+        let size = MAX_AGENTS as u64 * std::mem::size_of::<Agent>() as u64;
+
+        let storage = device.create_buffer(&BufferDescriptor {
             label: None,
             size,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let counters = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: std::mem::size_of::<AgentCounters>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let counters_readback = ReadbackBuffer::new(device, std::mem::size_of::<AgentCounters>() as u64);
+
+        let free_list = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: MAX_AGENTS as u64 * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let cells = create_cells_buffer(device, SimulationSize::default());
+
+        let dispatch = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 3 * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
             mapped_at_creation: false,
         });
 
-        let storage = device.create_buffer_with_data(&BufferInitDescriptor {
+        let stats = device.create_buffer(&BufferDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&[[0.0; 3]; NUM_AGENTS as usize]),
+            size: std::mem::size_of::<StatisticsSums>() as u64,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
 
+        let stats_readback = ReadbackBuffer::new(device, std::mem::size_of::<StatisticsSums>() as u64);
+
         Self {
             storage,
-            staging,
+            counters,
+            counters_readback,
+            free_list,
+            cells,
+            dispatch,
+            stats,
+            stats_readback,
+            size,
+        }
+    }
+}
+
+
+fn create_cells_buffer(device: &RenderDevice, size: SimulationSize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: size.texels() * std::mem::size_of::<u32>() as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+
+/// Render world buffers the size of the trail map.
+#[derive(Resource)]
+pub struct SlimeMoldTrailBuffers {
+    /// Fixed point trail deposits of the current step, resolved into the trail map every step.
+    pub deposits: Buffer,
+    /// Intermediate result of the separable diffusion blur.
+    pub blur: Texture,
+    pub blur_view: TextureView,
+    /// Both halves of the Gray-Scott reaction-diffusion state.
+    pub reaction: Buffer,
+    /// Per texel sums of agent heading and sensor disagreement, cleared every frame they are shown.
+    /// Only allocated while the display mode shows them, `splats_placeholder` is bound in their place otherwise.
+    pub agent_splats: Option<Buffer>,
+    pub splats_placeholder: Buffer,
+    pub size: SimulationSize,
+    /// The previous size for the frame the buffers were recreated in, agents are rescaled to the new size in that frame.
+    pub resized_from: Option<SimulationSize>,
+}
+
+impl FromWorld for SlimeMoldTrailBuffers {
+    fn from_world(world: &mut World) -> Self {
+        Self::new(world.resource::<RenderDevice>(), SimulationSize::default())
+    }
+}
+
+impl SlimeMoldTrailBuffers {
+    pub fn new(device: &RenderDevice, size: SimulationSize) -> Self {
+        let deposits = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: size.texels() * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let blur = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+        let blur_view = blur.create_view(&TextureViewDescriptor::default());
+
+        let reaction = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 2 * size.texels() * std::mem::size_of::<[f32; 2]>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let splats_placeholder = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 3 * std::mem::size_of::<i32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            deposits,
+            blur,
+            blur_view,
+            reaction,
+            agent_splats: None,
+            splats_placeholder,
             size,
+            resized_from: None,
         }
     }
+
+    pub fn agent_splats_binding(&self) -> BindingResource {
+        self.agent_splats.as_ref().unwrap_or(&self.splats_placeholder).as_entire_binding()
+    }
+}
+
+fn create_agent_splats_buffer(device: &RenderDevice, size: SimulationSize) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: 3 * size.texels() * std::mem::size_of::<i32>() as u64,
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// Recreates the buffers the size of the trail map when the simulation size changes,
+/// and allocates the agent splats while the display mode needs them.
+pub fn prepare_trail_buffers(
+    device: Res<RenderDevice>,
+    simulation_size: Res<SimulationSize>,
+    settings: Res<UISettings>,
+    mut agents_buf: ResMut<SlimeMoldAgentsBuffer>,
+    mut trail_buf: ResMut<SlimeMoldTrailBuffers>,
+) {
+    if trail_buf.size == *simulation_size {
+        trail_buf.resized_from = None;
+    } else {
+        let old_size = trail_buf.size;
+        *trail_buf = SlimeMoldTrailBuffers::new(&device, *simulation_size);
+        trail_buf.resized_from = Some(old_size);
+        agents_buf.cells = create_cells_buffer(&device, *simulation_size);
+    }
+
+    match (settings.display_mode.splats_agents(), trail_buf.agent_splats.is_some()) {
+        (true, false) => trail_buf.agent_splats = Some(create_agent_splats_buffer(&device, trail_buf.size)),
+        (false, true) => trail_buf.agent_splats = None,
+        _ => {}
+    }
 }
 
 
@@ -69,13 +267,76 @@ pub struct SettingsUniform {
     pub sensor_offset_dst: f32,
     pub sensor_size: i32,
 
-    color_a: Vec4,
-    color_b: Vec4,
+    brush_position: Vec2,
+    pub brush_radius: f32,
+    brush_strength: f32,
+
+    num_agents: u32,
+    max_agents: u32,
+    agents_dispatch_width: u32,
+    spawn_count: u32,
+
+    lifecycle: u32,
+    crowd_limit: u32,
+    lifetime: f32,
+    death_threshold: f32,
+    birth_threshold: f32,
+    birth_rate: f32,
+
+    occupancy_limited: u32,
+
+    diffusion_kernel: u32,
+    diffusion_radius: i32,
+    diffusion_sigma: f32,
+    diffusion_anisotropy: f32,
+
+    parameter_map_targets: UVec2,
+    parameter_map_strengths: Vec2,
+
+    sensor_model: u32,
+    sensor_count: u32,
+    sensor_noise: f32,
+
+    steering_model: u32,
+    steering_temperature: f32,
+    apply_new_angle: u32,
+
+    wind: Vec2,
+    flow_map_strength: f32,
+    flow_map_enabled: u32,
+
+    reaction_diffusion: u32,
+    feed_rate: f32,
+    kill_rate: f32,
+    diffusion_rate_u: f32,
+    diffusion_rate_v: f32,
+    reaction_speed: f32,
+    reaction_deposit: f32,
+    reaction_sensing: f32,
+    show_reaction: u32,
+
+    surface: u32,
+
+    display_mode: u32,
+    density_scale: f32,
+    hdr_intensity: f32,
+
+    /// Scale from the previous to the current size of the trail map, in the frame it is resized.
+    rescale: Vec2,
+
+    seed: u32,
+    stats_threshold: f32,
     
     // #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
     // _padding: f32,
 }
 
+/// Number of simulation steps run so far, counted by the simulation node in the render world.
+#[derive(Resource, Default)]
+pub struct SimulationClock {
+    pub tick: u64,
+}
+
 #[derive(Resource, Default)]
 pub struct SettingsBuffer {
     pub buffer: UniformBuffer<SettingsUniform>,
@@ -94,13 +355,25 @@ pub fn prepare_settings_buffer(
     queue: Res<RenderQueue>,
     mut settings_buffer: ResMut<SettingsBuffer>,
     settings: Res<UISettings>,
+    brush: Res<BrushInput>,
+    parameter_maps: Res<ParameterMapImages>,
+    flow_map: Res<FlowMapImage>,
+    gpu_images: Res<RenderAssets<Image>>,
+    trail_buf: Res<SlimeMoldTrailBuffers>,
+    clock: Res<SimulationClock>,
     time: Res<Time>,
 ) {
     let buffer = settings_buffer.buffer.get_mut();
-    buffer.delta_time = time.delta_seconds();
-    buffer.time = time.elapsed_seconds();
-    buffer.dim_x = TEXTURE_SIZE.0 as i32;
-    buffer.dim_y = TEXTURE_SIZE.1 as i32;
+    // A fixed time step makes a run repeatable from a checkpoint, as the time also seeds the random numbers.
+    if settings.deterministic {
+        buffer.delta_time = settings.time_step;
+        buffer.time = clock.tick as f32 * settings.time_step;
+    } else {
+        buffer.delta_time = time.delta_seconds();
+        buffer.time = time.elapsed_seconds();
+    }
+    buffer.dim_x = trail_buf.size.width as i32;
+    buffer.dim_y = trail_buf.size.height as i32;
     buffer.move_speed = settings.move_speed;
     buffer.turn_speed = settings.turn_speed;
     buffer.trail_weight = settings.trail_weight;
@@ -109,8 +382,57 @@ pub fn prepare_settings_buffer(
     buffer.sensor_angle_spacing = settings.sensor_angle_spacing;
     buffer.sensor_offset_dst = settings.sensor_offset_dst;
     buffer.sensor_size = settings.sensor_size;
-    buffer.color_a = Vec4::new(settings.color_a[0], settings.color_a[1], settings.color_a[2], 1.0);
-    buffer.color_b = Vec4::new(settings.color_b[0], settings.color_b[1], settings.color_b[2], 1.0);
+    buffer.sensor_model = settings.sensor_model as u32;
+    buffer.sensor_count = settings.sensor_count;
+    buffer.sensor_noise = settings.sensor_noise;
+    buffer.steering_model = settings.steering_model as u32;
+    buffer.steering_temperature = settings.steering_temperature;
+    buffer.apply_new_angle = settings.apply_new_angle as u32;
+    buffer.brush_position = brush.position;
+    buffer.brush_radius = settings.brush_radius;
+    buffer.brush_strength = brush.strength;
+    buffer.num_agents = NUM_AGENTS;
+    buffer.max_agents = MAX_AGENTS;
+    buffer.agents_dispatch_width = AGENTS_DISPATCH_WIDTH;
+    buffer.spawn_count = brush.spawn_count;
+    buffer.lifecycle = settings.lifecycle as u32;
+    buffer.crowd_limit = settings.crowd_limit;
+    buffer.lifetime = settings.lifetime;
+    buffer.death_threshold = settings.death_threshold;
+    buffer.birth_threshold = settings.birth_threshold;
+    buffer.birth_rate = settings.birth_rate;
+    buffer.occupancy_limited = settings.occupancy_limited as u32;
+    buffer.diffusion_kernel = settings.diffusion_kernel as u32;
+    buffer.diffusion_radius = settings.diffusion_radius;
+    buffer.diffusion_sigma = settings.diffusion_sigma;
+    buffer.diffusion_anisotropy = settings.diffusion_anisotropy;
+
+    let map_target = |i: usize| match parameter_maps.0[i] {
+        Some(_) => settings.parameter_maps[i].target,
+        None => MapTarget::None,
+    };
+    buffer.parameter_map_targets = UVec2::new(map_target(0) as u32, map_target(1) as u32);
+    buffer.parameter_map_strengths = Vec2::new(settings.parameter_maps[0].strength, settings.parameter_maps[1].strength);
+
+    buffer.wind = settings.wind();
+    buffer.flow_map_strength = settings.flow_map_strength;
+    buffer.flow_map_enabled = flow_map.0.as_ref().map_or(false, |handle| gpu_images.get(handle).is_some()) as u32;
+    buffer.reaction_diffusion = settings.reaction_diffusion as u32;
+    buffer.feed_rate = settings.feed_rate;
+    buffer.kill_rate = settings.kill_rate;
+    buffer.diffusion_rate_u = settings.diffusion_rate_u;
+    buffer.diffusion_rate_v = settings.diffusion_rate_v;
+    buffer.reaction_speed = settings.reaction_speed;
+    buffer.reaction_deposit = settings.reaction_deposit;
+    buffer.reaction_sensing = settings.reaction_sensing;
+    buffer.show_reaction = (settings.reaction_diffusion && settings.show_reaction) as u32;
+    buffer.surface = (settings.mode == SimulationMode::Sphere) as u32;
+    buffer.display_mode = settings.display_mode as u32;
+    buffer.density_scale = settings.density_scale;
+    buffer.hdr_intensity = settings.hdr_intensity;
+    buffer.rescale = trail_buf.resized_from.map_or(Vec2::ONE, |old_size| trail_buf.size.as_vec2() / old_size.as_vec2());
+    buffer.seed = settings.seed;
+    buffer.stats_threshold = settings.stats_threshold;
 
     settings_buffer.buffer.write_buffer(&device, &queue);
 }
\ No newline at end of file
@@ -0,0 +1,123 @@
+use bevy::{prelude::*, window::PrimaryWindow, input::mouse::{MouseWheel, MouseScrollUnit}, render::{texture::ImageSampler, extract_resource::ExtractResource, render_resource::Sampler, render_asset::RenderAssets, renderer::RenderDevice}, sprite::SpriteAssetEvents};
+use bevy_egui::EguiContexts;
+
+use super::texture::{DisplayImage, SlimeMoldSprite, SimulationSize};
+
+
+pub const MIN_ZOOM_SCALE: f32 = 0.02;
+pub const MAX_ZOOM_SCALE: f32 = 4.0;
+/// Zoom factor of one line of mouse wheel scrolling.
+const ZOOM_STEP: f32 = 1.15;
+
+/// Zooms the 2D camera around the cursor with the mouse wheel, pans it by dragging with the middle mouse button,
+/// and resets it with `R`.
+pub fn update_camera(
+    mut contexts: EguiContexts,
+    mut wheel_events: EventReader<MouseWheel>,
+    mouse: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut camera_query: Query<(&Camera, &GlobalTransform, &mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+    mut last_cursor: Local<Option<Vec2>>,
+) {
+    let scroll = wheel_events
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 40.0,
+        })
+        .sum::<f32>();
+    let cursor = window_query.get_single().ok().and_then(|window| window.cursor_position());
+    let last_cursor = std::mem::replace(&mut *last_cursor, cursor);
+
+    let Ok((camera, camera_transform, mut transform, mut projection)) = camera_query.get_single_mut() else { return };
+    if !camera.is_active { return; }
+
+    let ctx = contexts.ctx_mut();
+    if keyboard.just_pressed(KeyCode::R) && !ctx.wants_keyboard_input() {
+        transform.translation = Vec3::new(0.0, 0.0, transform.translation.z);
+        projection.scale = 1.0;
+        return;
+    }
+    if ctx.wants_pointer_input() || ctx.is_pointer_over_area() { return; }
+
+    // Pans by how far the cursor moved in the world, so the image stays under the cursor.
+    // Raw mouse motion would not match it, as it skips pointer acceleration and scaling.
+    let to_world = |position| camera.viewport_to_world_2d(camera_transform, position);
+    if mouse.pressed(MouseButton::Middle) {
+        if let (Some(from), Some(to)) = (last_cursor.and_then(to_world), cursor.and_then(to_world)) {
+            transform.translation += (from - to).extend(0.0);
+        }
+    }
+
+    if scroll != 0.0 {
+        let old_scale = projection.scale;
+        projection.scale = (old_scale * ZOOM_STEP.powf(-scroll)).clamp(MIN_ZOOM_SCALE, MAX_ZOOM_SCALE);
+
+        // Keeps the point under the cursor in place.
+        if let Some(cursor) = cursor.and_then(to_world) {
+            let offset = transform.translation.truncate() - cursor;
+            let translation = cursor + offset * projection.scale / old_scale;
+            transform.translation = translation.extend(transform.translation.z);
+        }
+    }
+}
+
+/// Whether the display image is sampled with nearest neighbour filtering.
+#[derive(Resource, Clone, Copy, Default, ExtractResource)]
+pub struct DisplaySampler {
+    pub nearest: bool,
+}
+
+/// Samples the display image with nearest neighbour filtering once a texel covers more than a physical pixel on screen,
+/// so individual texels stay sharp when zoomed in.
+pub fn update_display_sampler(
+    simulation_size: Res<SimulationSize>,
+    mut display_sampler: ResMut<DisplaySampler>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<&OrthographicProjection, With<Camera2d>>,
+    sprite_query: Query<&Sprite, With<SlimeMoldSprite>>,
+) {
+    let Ok(projection) = camera_query.get_single() else { return };
+    let Some(size) = sprite_query.get_single().ok().and_then(|sprite| sprite.custom_size) else { return };
+
+    let scale_factor = window_query.get_single().map_or(1.0, |window| window.scale_factor() as f32);
+    let texel_size = size.x * scale_factor / simulation_size.width as f32 / projection.scale;
+    if display_sampler.nearest != (texel_size > 1.0) {
+        display_sampler.nearest = texel_size > 1.0;
+    }
+}
+
+/// Both samplers of the display image, created once so switching between them leaves the image asset untouched.
+#[derive(Resource)]
+pub struct DisplaySamplers {
+    nearest: Sampler,
+    linear: Sampler,
+}
+
+impl FromWorld for DisplaySamplers {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        Self {
+            nearest: device.create_sampler(&ImageSampler::nearest_descriptor()),
+            linear: device.create_sampler(&ImageSampler::linear_descriptor()),
+        }
+    }
+}
+
+/// Swaps the sampler of the prepared display image, as changing the image asset would upload the whole texture again.
+/// Sprites only rebuild their bind group on image events, so the swap is reported to them as a modification.
+pub fn prepare_display_sampler(
+    display_image: Res<DisplayImage>,
+    display_sampler: Res<DisplaySampler>,
+    samplers: Res<DisplaySamplers>,
+    mut gpu_images: ResMut<RenderAssets<Image>>,
+    mut sprite_events: ResMut<SpriteAssetEvents>,
+) {
+    let Some(gpu_image) = gpu_images.get_mut(&display_image.0) else { return };
+    let sampler = if display_sampler.nearest { &samplers.nearest } else { &samplers.linear };
+    if gpu_image.sampler.id() == sampler.id() { return; }
+
+    gpu_image.sampler = sampler.clone();
+    sprite_events.images.push(AssetEvent::Modified { handle: display_image.0.clone_weak() });
+}
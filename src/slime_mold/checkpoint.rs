@@ -0,0 +1,577 @@
+use std::{fs::{self, File}, io::{self, Read, Write, BufReader, BufWriter}, path::Path};
+
+use bevy::{prelude::*, window::PrimaryWindow, asset::LoadState, tasks::IoTaskPool, render::{render_resource::{CommandEncoder, Extent3d, ImageDataLayout}, render_asset::RenderAssets, renderer::{RenderDevice, RenderQueue}}};
+
+use super::{MAX_AGENTS, texture::{SlimeMoldImage, SimulationSize, target_simulation_size}, buffers::{SlimeMoldAgentsBuffer, SlimeMoldTrailBuffers, SimulationClock, Agent, AgentCounters}, readback::{ReadbackBuffer, SimulationReadback, padded_bytes_per_row, strip_row_padding}, ui::{UISettings, SimulationMode, SteeringModel, DiffusionKernel, SensorModel}, parameter_maps::{ParameterMapImages, FlowMapImage, MapTarget}};
+
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"SLIMECKP";
+const CHECKPOINT_VERSION: u32 = 2;
+pub const CHECKPOINT_EXTENSION: &str = "ckpt";
+/// Autosaves overwrite a single file, so long runs do not fill the disk.
+pub const AUTOSAVE_PATH: &str = "checkpoint_autosave.ckpt";
+
+/// Everything needed to resume a run of the flat simulation: its GPU state, and the settings it was stepped with.
+#[derive(Clone)]
+pub struct SimulationSnapshot {
+    pub size: SimulationSize,
+    pub tick: u64,
+    /// Settings that affect how the simulation evolves, as `name value` lines.
+    pub settings: String,
+    pub counters: Vec<u8>,
+    pub agents: Vec<u8>,
+    pub free_list: Vec<u8>,
+    /// Trail map texels, without row padding.
+    pub trail: Vec<u8>,
+    /// The current half of the reaction-diffusion buffer.
+    pub reaction: Vec<u8>,
+}
+
+impl SimulationSnapshot {
+    /// Writes the magic string, the version, the size and tick, followed by length prefixed sections, all little endian.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(CHECKPOINT_MAGIC)?;
+        file.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+        file.write_all(&self.size.width.to_le_bytes())?;
+        file.write_all(&self.size.height.to_le_bytes())?;
+        file.write_all(&self.tick.to_le_bytes())?;
+        for section in [self.settings.as_bytes(), &self.counters, &self.agents, &self.free_list, &self.trail, &self.reaction] {
+            file.write_all(&(section.len() as u64).to_le_bytes())?;
+            file.write_all(section)?;
+        }
+        file.flush()
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if magic != *CHECKPOINT_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a checkpoint file"));
+        }
+        let version = read_u32(&mut file)?;
+        if version != CHECKPOINT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported checkpoint version {}", version)));
+        }
+
+        let size = SimulationSize {
+            width: read_u32(&mut file)?,
+            height: read_u32(&mut file)?,
+        };
+        let tick = read_u64(&mut file)?;
+        let settings = String::from_utf8(read_section(&mut file)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(Self {
+            size,
+            tick,
+            settings,
+            counters: read_section(&mut file)?,
+            agents: read_section(&mut file)?,
+            free_list: read_section(&mut file)?,
+            trail: read_section(&mut file)?,
+            reaction: read_section(&mut file)?,
+        })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_section(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let length = read_u64(reader)?;
+    let mut data = Vec::new();
+    reader.take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated checkpoint"));
+    }
+    Ok(data)
+}
+
+
+/// A setting stored in a checkpoint as text.
+trait CheckpointValue {
+    fn save(&self) -> String;
+    fn load(&mut self, value: &str) -> Option<()>;
+}
+
+macro_rules! impl_checkpoint_value {
+    ($($ty:ty),*) => {
+        $(impl CheckpointValue for $ty {
+            fn save(&self) -> String {
+                self.to_string()
+            }
+
+            fn load(&mut self, value: &str) -> Option<()> {
+                *self = value.parse().ok()?;
+                Some(())
+            }
+        })*
+    };
+}
+
+impl_checkpoint_value!(f32, i32, u32, bool, String);
+
+/// Enum settings are stored by their position in `VARIANTS`.
+trait CheckpointEnum: Copy + PartialEq + 'static {
+    const VARIANTS: &'static [Self];
+}
+
+impl<T: CheckpointEnum> CheckpointValue for T {
+    fn save(&self) -> String {
+        T::VARIANTS.iter().position(|value| value == self).unwrap_or(0).to_string()
+    }
+
+    fn load(&mut self, value: &str) -> Option<()> {
+        *self = *T::VARIANTS.get(value.parse::<usize>().ok()?)?;
+        Some(())
+    }
+}
+
+impl CheckpointEnum for SimulationMode { const VARIANTS: &'static [Self] = &SimulationMode::ALL; }
+impl CheckpointEnum for SteeringModel { const VARIANTS: &'static [Self] = &SteeringModel::ALL; }
+impl CheckpointEnum for DiffusionKernel { const VARIANTS: &'static [Self] = &DiffusionKernel::ALL; }
+impl CheckpointEnum for SensorModel { const VARIANTS: &'static [Self] = &SensorModel::ALL; }
+impl CheckpointEnum for MapTarget { const VARIANTS: &'static [Self] = &MapTarget::ALL; }
+
+/// Visits the settings that change how the simulation evolves. Display, brush and camera settings are left as they are.
+fn visit_checkpoint_settings(settings: &mut UISettings, mut visit: impl FnMut(&'static str, &mut dyn CheckpointValue)) {
+    let [map0, map1] = &mut settings.parameter_maps;
+    visit("mode", &mut settings.mode);
+    visit("move_speed", &mut settings.move_speed);
+    visit("turn_speed", &mut settings.turn_speed);
+    visit("steering_model", &mut settings.steering_model);
+    visit("steering_temperature", &mut settings.steering_temperature);
+    visit("apply_new_angle", &mut settings.apply_new_angle);
+    visit("trail_weight", &mut settings.trail_weight);
+    visit("decay_rate", &mut settings.decay_rate);
+    visit("diffuse_rate", &mut settings.diffuse_rate);
+    visit("diffusion_kernel", &mut settings.diffusion_kernel);
+    visit("diffusion_radius", &mut settings.diffusion_radius);
+    visit("diffusion_sigma", &mut settings.diffusion_sigma);
+    visit("diffusion_anisotropy", &mut settings.diffusion_anisotropy);
+    visit("sensor_angle_spacing", &mut settings.sensor_angle_spacing);
+    visit("sensor_offset_dst", &mut settings.sensor_offset_dst);
+    visit("sensor_size", &mut settings.sensor_size);
+    visit("sensor_model", &mut settings.sensor_model);
+    visit("sensor_count", &mut settings.sensor_count);
+    visit("sensor_noise", &mut settings.sensor_noise);
+    visit("occupancy_limited", &mut settings.occupancy_limited);
+    visit("lifecycle", &mut settings.lifecycle);
+    visit("lifetime", &mut settings.lifetime);
+    visit("crowd_limit", &mut settings.crowd_limit);
+    visit("death_threshold", &mut settings.death_threshold);
+    visit("birth_threshold", &mut settings.birth_threshold);
+    visit("birth_rate", &mut settings.birth_rate);
+    visit("parameter_map_0_path", &mut map0.path);
+    visit("parameter_map_0_target", &mut map0.target);
+    visit("parameter_map_0_strength", &mut map0.strength);
+    visit("parameter_map_1_path", &mut map1.path);
+    visit("parameter_map_1_target", &mut map1.target);
+    visit("parameter_map_1_strength", &mut map1.strength);
+    visit("wind_direction", &mut settings.wind_direction);
+    visit("wind_strength", &mut settings.wind_strength);
+    visit("flow_map_path", &mut settings.flow_map_path);
+    visit("flow_map_strength", &mut settings.flow_map_strength);
+    visit("reaction_diffusion", &mut settings.reaction_diffusion);
+    visit("feed_rate", &mut settings.feed_rate);
+    visit("kill_rate", &mut settings.kill_rate);
+    visit("diffusion_rate_u", &mut settings.diffusion_rate_u);
+    visit("diffusion_rate_v", &mut settings.diffusion_rate_v);
+    visit("reaction_speed", &mut settings.reaction_speed);
+    visit("reaction_deposit", &mut settings.reaction_deposit);
+    visit("reaction_sensing", &mut settings.reaction_sensing);
+    visit("deterministic", &mut settings.deterministic);
+    visit("time_step", &mut settings.time_step);
+    visit("seed", &mut settings.seed);
+}
+
+pub fn settings_to_text(settings: &UISettings) -> String {
+    let mut settings = settings.clone();
+    let mut text = String::new();
+    visit_checkpoint_settings(&mut settings, |name, value| {
+        text.push_str(&format!("{} {}\n", name, value.save()));
+    });
+    text
+}
+
+/// Applies the settings of a checkpoint, unknown names are skipped so older checkpoints keep loading.
+pub fn apply_settings_text(settings: &mut UISettings, text: &str) {
+    let values: Vec<(&str, &str)> = text.lines().filter_map(|line| line.split_once(' ')).collect();
+    visit_checkpoint_settings(settings, |name, field| {
+        let Some((_, value)) = values.iter().find(|(value_name, _)| *value_name == name) else { return };
+        if field.load(value).is_none() {
+            warn!("Ignoring invalid checkpoint setting {} = {}", name, value);
+        }
+    });
+}
+
+
+/// Staging buffers for the state of a checkpoint, only allocated while one is being saved.
+pub struct CheckpointBuffers {
+    size: SimulationSize,
+    counters: ReadbackBuffer,
+    agents: ReadbackBuffer,
+    free_list: ReadbackBuffer,
+    trail: ReadbackBuffer,
+    reaction: ReadbackBuffer,
+    data: [Option<Vec<u8>>; 5],
+}
+
+#[derive(Resource, Default)]
+pub struct CheckpointReadback(Option<CheckpointBuffers>);
+
+pub fn prepare_checkpoint_readback(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    trail_buf: Res<SlimeMoldTrailBuffers>,
+    mut checkpoint_readback: ResMut<CheckpointReadback>,
+) {
+    if readback.lock().unwrap().checkpoint_requested.is_none() { return; }
+    if checkpoint_readback.0.as_ref().map_or(false, |buffers| buffers.size == trail_buf.size) { return; }
+
+    let size = trail_buf.size;
+    checkpoint_readback.0 = Some(CheckpointBuffers {
+        size,
+        counters: ReadbackBuffer::new(&device, std::mem::size_of::<AgentCounters>() as u64),
+        agents: ReadbackBuffer::new(&device, MAX_AGENTS as u64 * std::mem::size_of::<Agent>() as u64),
+        free_list: ReadbackBuffer::new(&device, MAX_AGENTS as u64 * std::mem::size_of::<u32>() as u64),
+        trail: ReadbackBuffer::new(&device, padded_bytes_per_row(size.width) as u64 * size.height as u64),
+        reaction: ReadbackBuffer::new(&device, size.texels() * std::mem::size_of::<[f32; 2]>() as u64),
+        data: Default::default(),
+    });
+}
+
+/// Records the copies of a requested checkpoint. All copies are recorded in the same frame, so the state is consistent,
+/// and the settings are taken from that frame as well, so they match the state even if they change before it is saved.
+pub fn record_checkpoint(world: &World, encoder: &mut CommandEncoder) {
+    let Some(buffers) = &world.resource::<CheckpointReadback>().0 else { return };
+    let agents_buf = world.resource::<SlimeMoldAgentsBuffer>();
+    let trail_buf = world.resource::<SlimeMoldTrailBuffers>();
+    let mut readback = world.resource::<SimulationReadback>().lock().unwrap();
+    if readback.checkpoint_recorded.is_some() || buffers.size != trail_buf.size { return; }
+    let Some(path) = readback.checkpoint_requested.take() else { return };
+    let Some(trail_image) = world.resource::<RenderAssets<Image>>().get(&world.resource::<SlimeMoldImage>().0) else {
+        readback.checkpoint_requested = Some(path);
+        return;
+    };
+
+    buffers.counters.copy_from(encoder, &agents_buf.counters);
+    buffers.agents.copy_from(encoder, &agents_buf.storage);
+    buffers.free_list.copy_from(encoder, &agents_buf.free_list);
+    buffers.reaction.copy_from(encoder, &trail_buf.reaction);
+    let extent = Extent3d {
+        width: buffers.size.width,
+        height: buffers.size.height,
+        depth_or_array_layers: 1,
+    };
+    buffers.trail.copy_from_texture(encoder, &trail_image.texture, extent, padded_bytes_per_row(buffers.size.width));
+
+    readback.checkpoint_recorded = Some((path, SimulationSnapshot {
+        size: buffers.size,
+        tick: world.resource::<SimulationClock>().tick,
+        settings: settings_to_text(world.resource::<UISettings>()),
+        counters: Vec::new(),
+        agents: Vec::new(),
+        free_list: Vec::new(),
+        trail: Vec::new(),
+        reaction: Vec::new(),
+    }));
+}
+
+/// Collects the copies of a checkpoint once they are all mapped and hands the snapshot to the main world.
+pub fn readback_checkpoint(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    mut checkpoint_readback: ResMut<CheckpointReadback>,
+) {
+    let Some(buffers) = &mut checkpoint_readback.0 else { return };
+    let sources = [&buffers.counters, &buffers.agents, &buffers.free_list, &buffers.trail, &buffers.reaction];
+    for (data, source) in buffers.data.iter_mut().zip(sources) {
+        if data.is_none() {
+            *data = source.poll(&device);
+        }
+    }
+    if buffers.data.iter().any(Option::is_none) { return; }
+    let [Some(counters), Some(agents), Some(free_list), Some(padded_trail), Some(reaction)] = std::mem::take(&mut buffers.data) else { return };
+
+    let size = buffers.size;
+    let trail = strip_row_padding(&padded_trail, size.width);
+    checkpoint_readback.0 = None;
+
+    let mut readback = readback.lock().unwrap();
+    if let Some((path, recorded)) = readback.checkpoint_recorded.take() {
+        readback.checkpoint = Some((path, SimulationSnapshot {
+            counters,
+            agents,
+            free_list,
+            trail,
+            reaction,
+            ..recorded
+        }));
+    }
+}
+
+/// Writes a snapshot back to the GPU. Agent cells are recounted by the simulation node afterwards.
+pub fn restore_snapshot(world: &World, snapshot: &SimulationSnapshot) -> bool {
+    let agents_buf = world.resource::<SlimeMoldAgentsBuffer>();
+    let trail_buf = world.resource::<SlimeMoldTrailBuffers>();
+    let Some(trail_image) = world.resource::<RenderAssets<Image>>().get(&world.resource::<SlimeMoldImage>().0) else { return false };
+
+    if snapshot.size != trail_buf.size {
+        error!(
+            "Checkpoint trail map is {}x{}, but the simulation is {}x{}",
+            snapshot.size.width, snapshot.size.height, trail_buf.size.width, trail_buf.size.height,
+        );
+        return false;
+    }
+    let reaction_size = trail_buf.size.texels() * std::mem::size_of::<[f32; 2]>() as u64;
+    if snapshot.counters.len() != std::mem::size_of::<AgentCounters>()
+        || snapshot.agents.len() as u64 != agents_buf.size
+        || snapshot.free_list.len() != MAX_AGENTS as usize * std::mem::size_of::<u32>()
+        || snapshot.trail.len() as u64 != trail_buf.size.texels() * std::mem::size_of::<f32>() as u64
+        || snapshot.reaction.len() as u64 != reaction_size
+    {
+        error!("Checkpoint sections do not match the sizes of the simulation buffers");
+        return false;
+    }
+
+    let queue = world.resource::<RenderQueue>();
+    queue.write_buffer(&agents_buf.counters, 0, &snapshot.counters);
+    queue.write_buffer(&agents_buf.storage, 0, &snapshot.agents);
+    queue.write_buffer(&agents_buf.free_list, 0, &snapshot.free_list);
+    queue.write_buffer(&trail_buf.reaction, 0, &snapshot.reaction);
+    queue.write_buffer(&trail_buf.reaction, reaction_size, &snapshot.reaction);
+    queue.write_texture(
+        trail_image.texture.as_image_copy(),
+        &snapshot.trail,
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(snapshot.size.width * std::mem::size_of::<f32>() as u32),
+            rows_per_image: Some(snapshot.size.height),
+        },
+        Extent3d {
+            width: snapshot.size.width,
+            height: snapshot.size.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    true
+}
+
+
+/// A checkpoint file to load, set from the UI or the command line.
+#[derive(Resource, Default)]
+pub struct CheckpointLoad(pub Option<String>);
+
+/// Reads `--checkpoint <path>` to resume a run, and `--autosave <ticks>` to save a checkpoint periodically.
+pub fn apply_command_line(mut settings: ResMut<UISettings>, mut checkpoint_load: ResMut<CheckpointLoad>) {
+    let args: Vec<String> = std::env::args().collect();
+    for pair in args.windows(2) {
+        match pair[0].as_str() {
+            "--checkpoint" => {
+                checkpoint_load.0 = Some(pair[1].clone());
+                settings.running = true;
+            }
+            "--autosave" => match pair[1].parse() {
+                Ok(interval) => settings.autosave_interval = interval,
+                Err(_) => warn!("Invalid autosave interval {}", pair[1]),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Reads a checkpoint file, applies its settings and queues its state to be written back to the GPU.
+/// The state is held back until the maps it references have loaded, so the restored run steps with them from its first tick,
+/// and until the simulation has been resized for its settings, such as the equirectangular trail map of the sphere.
+pub fn load_checkpoint(
+    mut checkpoint_load: ResMut<CheckpointLoad>,
+    readback: Res<SimulationReadback>,
+    asset_server: Res<AssetServer>,
+    simulation_size: Res<SimulationSize>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut parameter_map_images: ResMut<ParameterMapImages>,
+    mut flow_map_image: ResMut<FlowMapImage>,
+    mut settings: ResMut<UISettings>,
+    mut pending: Local<Option<SimulationSnapshot>>,
+) {
+    if let Some(path) = checkpoint_load.0.take() {
+        match SimulationSnapshot::read(&path) {
+            Ok(snapshot) => {
+                apply_settings_text(&mut settings, &snapshot.settings);
+                for (i, map) in settings.parameter_maps.iter().enumerate() {
+                    parameter_map_images.0[i] = (!map.path.is_empty()).then(|| asset_server.load(map.path.clone()));
+                }
+                flow_map_image.0 = (!settings.flow_map_path.is_empty()).then(|| asset_server.load(settings.flow_map_path.clone()));
+
+                info!("Loaded checkpoint {} at tick {}", path, snapshot.tick);
+                *pending = Some(snapshot);
+            }
+            Err(err) => error!("Failed to load checkpoint {}: {}", path, err),
+        }
+    }
+    let Some(snapshot) = pending.as_ref() else { return };
+    let reachable = target_simulation_size(&settings, window_query.get_single().ok()) == snapshot.size;
+    if reachable && *simulation_size != snapshot.size { return; }
+
+    let maps = parameter_map_images.0.iter().chain(std::iter::once(&flow_map_image.0)).flatten();
+    let load_states: Vec<LoadState> = maps.map(|map| asset_server.get_load_state(map)).collect();
+    if load_states.iter().any(|state| matches!(state, LoadState::Failed)) {
+        warn!("A map of the checkpoint failed to load, the restored run will not match the original");
+    } else if load_states.iter().any(|state| !matches!(state, LoadState::Loaded)) {
+        return;
+    }
+    readback.lock().unwrap().restore = pending.take();
+}
+
+/// Requests a checkpoint every `autosave_interval` ticks.
+pub fn autosave_checkpoint(
+    settings: Res<UISettings>,
+    readback: Res<SimulationReadback>,
+    mut last_tick: Local<u64>,
+) {
+    let mut readback = readback.lock().unwrap();
+    let interval = settings.autosave_interval as u64;
+    if interval > 0 && readback.tick / interval > *last_tick / interval && readback.checkpoint_requested.is_none() {
+        readback.checkpoint_requested = Some(AUTOSAVE_PATH.to_string());
+    }
+    *last_tick = readback.tick;
+}
+
+/// Writes checkpoints read back from the GPU to disk, off the main thread.
+/// The file is written next to its destination first, so an interrupted save never leaves a broken checkpoint behind.
+pub fn save_checkpoints(readback: Res<SimulationReadback>) {
+    let Some((path, snapshot)) = readback.lock().unwrap().checkpoint.take() else { return };
+
+    IoTaskPool::get().spawn(async move {
+        let temp_path = format!("{}.tmp", path);
+        match snapshot.write(&temp_path).and_then(|()| fs::rename(&temp_path, &path)) {
+            Ok(()) => info!("Saved checkpoint at tick {} to {}", snapshot.tick, path),
+            Err(err) => error!("Failed to save checkpoint to {}: {}", path, err),
+        }
+    }).detach();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("slime_mold_{}_{}.{}", name, std::process::id(), CHECKPOINT_EXTENSION))
+    }
+
+    fn test_snapshot() -> SimulationSnapshot {
+        SimulationSnapshot {
+            size: SimulationSize { width: 4, height: 2 },
+            tick: 1234,
+            settings: "move_speed 42\nseed 7\n".to_string(),
+            counters: vec![1, 2, 3, 4],
+            agents: (0..48).collect(),
+            free_list: Vec::new(),
+            trail: (0..32).map(|i| i * 3).collect(),
+            reaction: vec![9; 64],
+        }
+    }
+
+    /// Writes the test snapshot, lets `corrupt` edit the file and reads it back.
+    fn read_corrupted(name: &str, corrupt: impl FnOnce(&mut Vec<u8>)) -> io::Result<SimulationSnapshot> {
+        let path = temp_path(name);
+        test_snapshot().write(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        corrupt(&mut bytes);
+        fs::write(&path, bytes).unwrap();
+        let result = SimulationSnapshot::read(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_a_file() {
+        let path = temp_path("round_trip");
+        let snapshot = test_snapshot();
+        snapshot.write(&path).unwrap();
+        let read = SimulationSnapshot::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.size, snapshot.size);
+        assert_eq!(read.tick, snapshot.tick);
+        assert_eq!(read.settings, snapshot.settings);
+        assert_eq!(read.counters, snapshot.counters);
+        assert_eq!(read.agents, snapshot.agents);
+        assert_eq!(read.free_list, snapshot.free_list);
+        assert_eq!(read.trail, snapshot.trail);
+        assert_eq!(read.reaction, snapshot.reaction);
+    }
+
+    #[test]
+    fn read_rejects_bad_magic() {
+        let err = read_corrupted("bad_magic", |bytes| bytes[0] = b'X').err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_rejects_unsupported_version() {
+        let err = read_corrupted("bad_version", |bytes| bytes[8..12].copy_from_slice(&(CHECKPOINT_VERSION + 1).to_le_bytes())).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_rejects_truncated_files() {
+        let err = read_corrupted("truncated_section", |bytes| bytes.truncate(bytes.len() - 1)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let err = read_corrupted("truncated_header", |bytes| bytes.truncate(16)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn settings_round_trip_through_text() {
+        let mut settings = UISettings::default();
+        settings.mode = SimulationMode::Sphere;
+        settings.move_speed = 12.5;
+        settings.sensor_size = -3;
+        settings.sensor_count = 5;
+        settings.steering_model = SteeringModel::Gradient;
+        settings.reaction_diffusion = true;
+        settings.parameter_maps[1].path = "maps/with space.png".to_string();
+        settings.parameter_maps[1].target = MapTarget::DecayRate;
+        settings.seed = 99;
+
+        let text = settings_to_text(&settings);
+        let mut loaded = UISettings::default();
+        apply_settings_text(&mut loaded, &text);
+
+        assert_eq!(settings_to_text(&loaded), text);
+        assert_eq!(loaded.mode, SimulationMode::Sphere);
+        assert_eq!(loaded.move_speed, 12.5);
+        assert_eq!(loaded.sensor_size, -3);
+        assert_eq!(loaded.steering_model, SteeringModel::Gradient);
+        assert!(loaded.reaction_diffusion);
+        assert_eq!(loaded.parameter_maps[1].path, "maps/with space.png");
+        assert_eq!(loaded.parameter_maps[1].target, MapTarget::DecayRate);
+        assert_eq!(loaded.parameter_maps[0].path, "");
+        assert_eq!(loaded.seed, 99);
+    }
+
+    #[test]
+    fn apply_settings_text_skips_unknown_and_invalid_values() {
+        let mut settings = UISettings::default();
+        let default_turn_speed = settings.turn_speed;
+        apply_settings_text(&mut settings, "unknown_setting 1\nmove_speed 3.5\nturn_speed fast\nsteering_model 17\n");
+
+        assert_eq!(settings.move_speed, 3.5);
+        assert_eq!(settings.turn_speed, default_turn_speed);
+        assert_eq!(settings.steering_model, UISettings::default().steering_model);
+    }
+}
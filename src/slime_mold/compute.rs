@@ -1,48 +1,133 @@
 use std::borrow::Cow;
 
-use bevy::{prelude::*, render::{render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, BufferBindingType, BufferSize, PipelineCache, ComputePipelineDescriptor, CachedPipelineState, ComputePassDescriptor}, render_asset::RenderAssets, renderer::{RenderDevice, RenderContext}, render_graph}};
+use bevy::{prelude::*, render::{render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BindGroupLayout, CachedComputePipelineId, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, TextureFormat, TextureViewDimension, BufferBindingType, BufferSize, PipelineCache, ComputePipelineDescriptor, CachedPipelineState, ComputePassDescriptor, ComputePass, Sampler, SamplerDescriptor, SamplerBindingType, FilterMode, TextureSampleType, Extent3d}, render_asset::RenderAssets, texture::FallbackImage, renderer::{RenderDevice, RenderContext}, render_graph}};
 
-use super::{NUM_AGENTS, TEX_WORKGROUP_SIZE, AGENTS_WORKGROUP_SIZE, TEXTURE_SIZE, texture::SlimeMoldImage, buffers::{SlimeMoldAgentsBuffer, SettingsBuffer}, ui::UISettings, INITIAL_STATE};
+use super::{MAX_AGENTS, agent_workgroups, texture::{SlimeMoldImage, DisplayImage}, buffers::{SlimeMoldAgentsBuffer, SlimeMoldTrailBuffers, SettingsBuffer, SimulationClock, Agent}, readback::{SimulationReadback, AgentsReadback, TrailReadback, TrailExport, padded_bytes_per_row}, checkpoint::{record_checkpoint, restore_snapshot}, ui::{UISettings, SimulationMode}, brush::{BrushInput, BrushTool}, parameter_maps::{ParameterMapImages, FlowMapImage}, gradient::GradientImage, INITIAL_STATE};
 
 
 #[derive(Resource)]
-struct SlimeMoldBindGroups(BindGroup, BindGroup, BindGroup);
+struct SlimeMoldBindGroups(BindGroup, BindGroup, BindGroup, BindGroup, BindGroup);
 
 pub fn queue_bind_group(
     mut commands: Commands,
     pipeline: Res<SlimeMoldPipeline>,
     gpu_images: Res<RenderAssets<Image>>,
     slime_mold_image: Res<SlimeMoldImage>,
+    display_image: Res<DisplayImage>,
     slime_mold_agents_buf: Res<SlimeMoldAgentsBuffer>,
+    slime_mold_trail_buf: Res<SlimeMoldTrailBuffers>,
     slime_mold_settings: Res<SettingsBuffer>,
+    parameter_maps: Res<ParameterMapImages>,
+    flow_map: Res<FlowMapImage>,
+    gradient_image: Res<GradientImage>,
+    fallback_image: Res<FallbackImage>,
     render_device: Res<RenderDevice>,
 ) {
     let view = &gpu_images[&slime_mold_image.0];
+    let display_view = &gpu_images[&display_image.0];
     let bind_group_tex = render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: &pipeline.texture_bind_group_layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::TextureView(&view.texture_view),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: slime_mold_trail_buf.deposits.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&slime_mold_trail_buf.blur_view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: slime_mold_trail_buf.reaction.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::TextureView(&display_view.texture_view),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: slime_mold_trail_buf.agent_splats_binding(),
+            },
+        ],
     });
     let bind_group_buf = render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: &pipeline.agent_buf_bind_group_layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: slime_mold_agents_buf.storage.as_entire_binding(),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: slime_mold_agents_buf.storage.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: slime_mold_agents_buf.counters.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: slime_mold_agents_buf.free_list.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: slime_mold_agents_buf.cells.as_entire_binding(),
+            },
+        ],
     });
+    // Maps that are unused or still loading are bound to a white fallback image.
+    let map_view = |map: Option<&Handle<Image>>| map
+        .and_then(|handle| gpu_images.get(handle))
+        .map_or(&fallback_image.d2.texture_view, |image| &image.texture_view);
     let bind_group_settings = render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: &pipeline.settings_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: slime_mold_settings.buffer.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(map_view(parameter_maps.0[0].as_ref())),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(map_view(parameter_maps.0[1].as_ref())),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Sampler(&pipeline.parameter_map_sampler),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::TextureView(map_view(flow_map.0.as_ref())),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: BindingResource::TextureView(map_view(Some(&gradient_image.0))),
+            },
+        ],
+    });
+    let bind_group_dispatch = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.dispatch_bind_group_layout,
         entries: &[BindGroupEntry {
             binding: 0,
-            resource: slime_mold_settings.buffer.binding().unwrap(),
+            resource: slime_mold_agents_buf.dispatch.as_entire_binding(),
         }],
     });
-    commands.insert_resource(SlimeMoldBindGroups(bind_group_tex, bind_group_buf, bind_group_settings));
+    let bind_group_stats = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.stats_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 1,
+            resource: slime_mold_agents_buf.stats.as_entire_binding(),
+        }],
+    });
+    commands.insert_resource(SlimeMoldBindGroups(bind_group_tex, bind_group_buf, bind_group_settings, bind_group_dispatch, bind_group_stats));
 }
 
 #[derive(Resource)]
@@ -50,9 +135,29 @@ pub struct SlimeMoldPipeline {
     texture_bind_group_layout: BindGroupLayout,
     agent_buf_bind_group_layout: BindGroupLayout,
     settings_bind_group_layout: BindGroupLayout,
+    dispatch_bind_group_layout: BindGroupLayout,
+    stats_bind_group_layout: BindGroupLayout,
+    parameter_map_sampler: Sampler,
     init_pipeline: CachedComputePipelineId,
+    init_reaction_pipeline: CachedComputePipelineId,
+    rescale_agents_pipeline: CachedComputePipelineId,
+    prepare_dispatch_pipeline: CachedComputePipelineId,
     update_agents_pipeline: CachedComputePipelineId,
+    resolve_deposits_pipeline: CachedComputePipelineId,
+    advect_trailmap_pipeline: CachedComputePipelineId,
+    apply_advection_pipeline: CachedComputePipelineId,
+    react_forward_pipeline: CachedComputePipelineId,
+    react_backward_pipeline: CachedComputePipelineId,
+    blur_trailmap_pipeline: CachedComputePipelineId,
     update_trailmap_pipeline: CachedComputePipelineId,
+    paint_trailmap_pipeline: CachedComputePipelineId,
+    splat_agents_pipeline: CachedComputePipelineId,
+    display_trailmap_pipeline: CachedComputePipelineId,
+    spawn_agents_pipeline: CachedComputePipelineId,
+    erase_agents_pipeline: CachedComputePipelineId,
+    reproduce_agents_pipeline: CachedComputePipelineId,
+    reduce_trail_pipeline: CachedComputePipelineId,
+    reduce_agents_pipeline: CachedComputePipelineId,
 }
 
 impl FromWorld for SlimeMoldPipeline {
@@ -63,18 +168,194 @@ impl FromWorld for SlimeMoldPipeline {
             render_device
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: None,
-                    entries: &[BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::COMPUTE,
-                        ty: BindingType::StorageTexture {
-                            access: StorageTextureAccess::ReadWrite,
-                            format: TextureFormat::Rgba8Unorm,
-                            view_dimension: TextureViewDimension::D2,
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::ReadWrite,
+                                format: TextureFormat::R32Float,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
                         },
-                        count: None,
-                    }],
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::ReadWrite,
+                                format: TextureFormat::R32Float,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: TextureFormat::Rgba16Float,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
                 });
         let agent_buf_bind_group_layout = 
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(MAX_AGENTS as u64 * std::mem::size_of::<Agent>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(MAX_AGENTS as u64 * std::mem::size_of::<u32>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ]
+                });
+        let settings_bind_group_layout = 
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 5,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                    ]
+                });
+        let dispatch_bind_group_layout = 
             render_device
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: None,
@@ -86,62 +367,102 @@ impl FromWorld for SlimeMoldPipeline {
                                 read_only: false,
                             },
                             has_dynamic_offset: false,
-                            min_binding_size: BufferSize::new((NUM_AGENTS * 3 * std::mem::size_of::<f32>() as u32) as u64),
+                            min_binding_size: None,
                         },
                         count: None,
                     }]
                 });
-        let settings_bind_group_layout = 
+        // Uses the slot of the dispatch arguments, which keeps the reductions within eight storage buffers.
+        let stats_bind_group_layout = 
             render_device
                 .create_bind_group_layout(&BindGroupLayoutDescriptor {
                     label: None,
                     entries: &[BindGroupLayoutEntry {
-                        binding: 0,
+                        binding: 1,
                         visibility: ShaderStages::COMPUTE,
                         ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
+                            ty: BufferBindingType::Storage {
+                                read_only: false,
+                            },
                             has_dynamic_offset: false,
                             min_binding_size: None,
                         },
                         count: None,
                     }]
                 });
+        let parameter_map_sampler = render_device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
         let shader = world
             .resource::<AssetServer>()
             .load("shaders/slime_mold.wgsl");
         let pipeline_cache = world.resource::<PipelineCache>();
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from(INITIAL_STATE),
-        });
-        let update_agents_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("updateAgents"),
-        });
-        let update_trailmap_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone()],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("updateTrailmap"),
-        });
+        let layout = vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone()];
+        let queue_pipeline = |layout: Vec<BindGroupLayout>, entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: None,
+                layout,
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+
+        let init_pipeline = queue_pipeline(layout.clone(), INITIAL_STATE);
+        let init_reaction_pipeline = queue_pipeline(layout.clone(), "initReaction");
+        let rescale_agents_pipeline = queue_pipeline(layout.clone(), "rescaleAgents");
+        let prepare_dispatch_pipeline = queue_pipeline(
+            vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone(), dispatch_bind_group_layout.clone()],
+            "prepareDispatch",
+        );
+        let update_agents_pipeline = queue_pipeline(layout.clone(), "updateAgents");
+        let resolve_deposits_pipeline = queue_pipeline(layout.clone(), "resolveDeposits");
+        let advect_trailmap_pipeline = queue_pipeline(layout.clone(), "advectTrailmap");
+        let apply_advection_pipeline = queue_pipeline(layout.clone(), "applyAdvection");
+        let react_forward_pipeline = queue_pipeline(layout.clone(), "reactForward");
+        let react_backward_pipeline = queue_pipeline(layout.clone(), "reactBackward");
+        let blur_trailmap_pipeline = queue_pipeline(layout.clone(), "blurTrailmap");
+        let update_trailmap_pipeline = queue_pipeline(layout.clone(), "updateTrailmap");
+        let paint_trailmap_pipeline = queue_pipeline(layout.clone(), "paintTrailmap");
+        let splat_agents_pipeline = queue_pipeline(layout.clone(), "splatAgents");
+        let display_trailmap_pipeline = queue_pipeline(layout.clone(), "displayTrailmap");
+        let spawn_agents_pipeline = queue_pipeline(layout.clone(), "spawnAgents");
+        let erase_agents_pipeline = queue_pipeline(layout.clone(), "eraseAgents");
+        let reproduce_agents_pipeline = queue_pipeline(layout, "reproduceAgents");
+        let stats_layout = vec![texture_bind_group_layout.clone(), agent_buf_bind_group_layout.clone(), settings_bind_group_layout.clone(), stats_bind_group_layout.clone()];
+        let reduce_trail_pipeline = queue_pipeline(stats_layout.clone(), "reduceTrail");
+        let reduce_agents_pipeline = queue_pipeline(stats_layout, "reduceAgents");
 
         SlimeMoldPipeline {
             texture_bind_group_layout,
             agent_buf_bind_group_layout,
             settings_bind_group_layout,
+            dispatch_bind_group_layout,
+            stats_bind_group_layout,
+            parameter_map_sampler,
             init_pipeline,
+            init_reaction_pipeline,
+            rescale_agents_pipeline,
+            prepare_dispatch_pipeline,
             update_agents_pipeline,
+            resolve_deposits_pipeline,
+            advect_trailmap_pipeline,
+            apply_advection_pipeline,
+            react_forward_pipeline,
+            react_backward_pipeline,
+            blur_trailmap_pipeline,
             update_trailmap_pipeline,
+            paint_trailmap_pipeline,
+            splat_agents_pipeline,
+            display_trailmap_pipeline,
+            spawn_agents_pipeline,
+            erase_agents_pipeline,
+            reproduce_agents_pipeline,
+            reduce_trail_pipeline,
+            reduce_agents_pipeline,
         }
     }
 }
@@ -155,12 +476,15 @@ enum SlimeMoldState {
 
 pub struct SlimeMoldNode {
     state: SlimeMoldState,
+    /// Whether a checkpoint was written to the buffers this frame.
+    restored: bool,
 }
 
 impl Default for SlimeMoldNode {
     fn default() -> Self {
         Self {
             state: SlimeMoldState::Loading,
+            restored: false,
         }
     }
 }
@@ -172,34 +496,50 @@ impl render_graph::Node for SlimeMoldNode {
 
         match self.state {
             SlimeMoldState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline)
-                {
+                let ready = |id| matches!(pipeline_cache.get_compute_pipeline_state(id), CachedPipelineState::Ok(_));
+                if ready(pipeline.init_pipeline) && ready(pipeline.init_reaction_pipeline) {
                     self.state = SlimeMoldState::Init;
                 }
             }
             SlimeMoldState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_agents_pipeline)
-                {
+                let ready = |id| matches!(pipeline_cache.get_compute_pipeline_state(id), CachedPipelineState::Ok(_));
+                if ready(pipeline.update_agents_pipeline) && ready(pipeline.rescale_agents_pipeline) && ready(pipeline.splat_agents_pipeline) && ready(pipeline.display_trailmap_pipeline) {
                     self.state = SlimeMoldState::Waiting;
                 }
             }
             SlimeMoldState::Waiting => {
                 if let Some(ui_settings) = world.get_resource::<UISettings>() {
-                    if ui_settings.running {
+                    if ui_settings.running && ui_settings.mode != SimulationMode::Volume {
                         self.state = SlimeMoldState::Update;
                     }
                 }
             }
             SlimeMoldState::Update => {
                 if let Some(ui_settings) = world.get_resource::<UISettings>() {
-                    if !ui_settings.running {
+                    if !ui_settings.running || ui_settings.mode == SimulationMode::Volume {
                         self.state = SlimeMoldState::Waiting;
                     }
                 }
             }
         }
+
+        // The settings of this frame were prepared for the old tick, so the restored state only steps from the next frame.
+        self.restored = false;
+        if let SlimeMoldState::Waiting | SlimeMoldState::Update = self.state {
+            let restore = world.resource::<SimulationReadback>().lock().unwrap().restore.take();
+            if let Some(snapshot) = restore {
+                self.restored = restore_snapshot(world, &snapshot);
+                if self.restored {
+                    world.resource_mut::<SimulationClock>().tick = snapshot.tick;
+                    self.state = SlimeMoldState::Waiting;
+                }
+            }
+        }
+
+        if let SlimeMoldState::Update = self.state {
+            world.resource_mut::<SimulationClock>().tick += 1;
+        }
+        world.resource::<SimulationReadback>().lock().unwrap().tick = world.resource::<SimulationClock>().tick;
     }
 
     fn run(
@@ -211,17 +551,52 @@ impl render_graph::Node for SlimeMoldNode {
         let texture_bind_group = &world.resource::<SlimeMoldBindGroups>().0;
         let agents_buf_bind_group = &world.resource::<SlimeMoldBindGroups>().1;
         let settings_bind_group = &world.resource::<SlimeMoldBindGroups>().2;
+        let dispatch_bind_group = &world.resource::<SlimeMoldBindGroups>().3;
+        let stats_bind_group = &world.resource::<SlimeMoldBindGroups>().4;
         let agents_buf = &world.resource::<SlimeMoldAgentsBuffer>();
+        let trail_buf = &world.resource::<SlimeMoldTrailBuffers>();
+        let brush = world.resource::<BrushInput>();
+        let settings = world.resource::<UISettings>();
+        let flow_map = world.resource::<FlowMapImage>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<SlimeMoldPipeline>();
 
+        let (texture_x, texture_y) = trail_buf.size.workgroups();
+
         let encoder = render_context.command_encoder();
+        if matches!(self.state, SlimeMoldState::Init) || self.restored {
+            encoder.clear_buffer(&agents_buf.cells, 0, None);
+            encoder.clear_buffer(&trail_buf.deposits, 0, None);
+        }
+        if let Some(agent_splats) = &trail_buf.agent_splats {
+            encoder.clear_buffer(agent_splats, 0, None);
+        }
         {
             let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
             pass.set_bind_group(0, texture_bind_group, &[]);
             pass.set_bind_group(1, agents_buf_bind_group, &[]);
             pass.set_bind_group(2, settings_bind_group, &[]);
+            pass.set_bind_group(3, dispatch_bind_group, &[]);
+
+            // The buffers were recreated for a new trail map size, so agents are moved to match it and the reaction restarts.
+            // Restored agents are counted into their cells the same way, with a scale of one.
+            let resized = matches!(self.state, SlimeMoldState::Waiting | SlimeMoldState::Update) && trail_buf.resized_from.is_some();
+            if resized || self.restored {
+                let rescale_agents_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.rescale_agents_pipeline)
+                    .unwrap();
+                let (x, y) = agent_workgroups(MAX_AGENTS);
+                pass.set_pipeline(rescale_agents_pipeline);
+                pass.dispatch_workgroups(x, y, 1);
+            }
+            if resized {
+                let init_reaction_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.init_reaction_pipeline)
+                    .unwrap();
+                pass.set_pipeline(init_reaction_pipeline);
+                pass.dispatch_workgroups(texture_x, texture_y, 1);
+            }
 
             match self.state {
                 SlimeMoldState::Loading => {}
@@ -229,28 +604,220 @@ impl render_graph::Node for SlimeMoldNode {
                     let init_pipeline = pipeline_cache
                         .get_compute_pipeline(pipeline.init_pipeline)
                         .unwrap();
+                    let (x, y) = agent_workgroups(MAX_AGENTS);
                     pass.set_pipeline(init_pipeline);
-                    pass.dispatch_workgroups(NUM_AGENTS / AGENTS_WORKGROUP_SIZE, 1, 1);
+                    pass.dispatch_workgroups(x, y, 1);
+
+                    let init_reaction_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.init_reaction_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(init_reaction_pipeline);
+                    pass.dispatch_workgroups(texture_x, texture_y, 1);
+                }
+                SlimeMoldState::Waiting => {
+                    let prepare_dispatch_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.prepare_dispatch_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(prepare_dispatch_pipeline);
+                    pass.dispatch_workgroups(1, 1, 1);
+
+                    dispatch_brush(&mut pass, brush, pipeline, pipeline_cache, agents_buf, trail_buf);
                 }
-                SlimeMoldState::Waiting => {}
                 SlimeMoldState::Update => {
+                    let prepare_dispatch_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.prepare_dispatch_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(prepare_dispatch_pipeline);
+                    pass.dispatch_workgroups(1, 1, 1);
+
                     let update_agents_pipeline = pipeline_cache
                         .get_compute_pipeline(pipeline.update_agents_pipeline)
                         .unwrap();
                     pass.set_pipeline(update_agents_pipeline);
-                    pass.dispatch_workgroups(NUM_AGENTS / AGENTS_WORKGROUP_SIZE, 1, 1);
+                    pass.dispatch_workgroups_indirect(&agents_buf.dispatch, 0);
+
+                    let resolve_deposits_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.resolve_deposits_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(resolve_deposits_pipeline);
+                    pass.dispatch_workgroups(texture_x, texture_y, 1);
+
+                    if settings.wind_strength != 0.0 || flow_map.0.is_some() {
+                        let advect_trailmap_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.advect_trailmap_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(advect_trailmap_pipeline);
+                        pass.dispatch_workgroups(texture_x, texture_y, 1);
+
+                        let apply_advection_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.apply_advection_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(apply_advection_pipeline);
+                        pass.dispatch_workgroups(texture_x, texture_y, 1);
+                    }
+
+                    if settings.reaction_diffusion {
+                        let react_forward_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.react_forward_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(react_forward_pipeline);
+                        pass.dispatch_workgroups(texture_x, texture_y, 1);
+
+                        let react_backward_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.react_backward_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(react_backward_pipeline);
+                        pass.dispatch_workgroups(texture_x, texture_y, 1);
+                    }
+
+                    let blur_trailmap_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.blur_trailmap_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(blur_trailmap_pipeline);
+                    pass.dispatch_workgroups(texture_x, texture_y, 1);
 
                     let update_trailmap_pipeline = pipeline_cache
                         .get_compute_pipeline(pipeline.update_trailmap_pipeline)
                         .unwrap();
                     pass.set_pipeline(update_trailmap_pipeline);
-                    pass.dispatch_workgroups(TEXTURE_SIZE.0 / TEX_WORKGROUP_SIZE, TEXTURE_SIZE.1 / TEX_WORKGROUP_SIZE, 1);
+                    pass.dispatch_workgroups(texture_x, texture_y, 1);
+
+                    if settings.lifecycle {
+                        let reproduce_agents_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.reproduce_agents_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(reproduce_agents_pipeline);
+                        pass.dispatch_workgroups_indirect(&agents_buf.dispatch, 0);
+                    }
+
+                    dispatch_brush(&mut pass, brush, pipeline, pipeline_cache, agents_buf, trail_buf);
+                }
+            }
+
+            if let SlimeMoldState::Waiting | SlimeMoldState::Update = self.state {
+                if settings.display_mode.splats_agents() {
+                    let splat_agents_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.splat_agents_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(splat_agents_pipeline);
+                    pass.dispatch_workgroups_indirect(&agents_buf.dispatch, 0);
+                }
+
+                let display_trailmap_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.display_trailmap_pipeline)
+                    .unwrap();
+                pass.set_pipeline(display_trailmap_pipeline);
+                pass.dispatch_workgroups(texture_x, texture_y, 1);
+            }
+        }
+
+        agents_buf.counters_readback.copy_from(encoder, &agents_buf.counters);
+
+        if let SlimeMoldState::Waiting | SlimeMoldState::Update = self.state {
+            let mut readback = world.resource::<SimulationReadback>().lock().unwrap();
+            let ready = |id| matches!(pipeline_cache.get_compute_pipeline_state(id), CachedPipelineState::Ok(_));
+            if readback.stats_requested && ready(pipeline.reduce_trail_pipeline) && ready(pipeline.reduce_agents_pipeline) && agents_buf.stats_readback.is_idle() {
+                encoder.clear_buffer(&agents_buf.stats, 0, None);
+                {
+                    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+
+                    pass.set_bind_group(0, texture_bind_group, &[]);
+                    pass.set_bind_group(1, agents_buf_bind_group, &[]);
+                    pass.set_bind_group(2, settings_bind_group, &[]);
+                    pass.set_bind_group(3, stats_bind_group, &[]);
+
+                    let reduce_trail_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.reduce_trail_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(reduce_trail_pipeline);
+                    pass.dispatch_workgroups(texture_x, texture_y, 1);
+
+                    let reduce_agents_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.reduce_agents_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(reduce_agents_pipeline);
+                    pass.dispatch_workgroups_indirect(&agents_buf.dispatch, 0);
                 }
+                agents_buf.stats_readback.copy_from(encoder, &agents_buf.stats);
+                readback.stats_requested = false;
+                readback.stats_recorded = Some((world.resource::<SimulationClock>().tick, trail_buf.size.texels()));
             }
         }
 
-        encoder.copy_buffer_to_buffer(&agents_buf.storage, 0, &agents_buf.staging, 0, agents_buf.size);
+        if let SlimeMoldState::Waiting | SlimeMoldState::Update = self.state {
+            record_checkpoint(world, encoder);
+
+            let mut readback = world.resource::<SimulationReadback>().lock().unwrap();
+            // Staging buffers are allocated in the frame after a request made while this frame was being prepared.
+            let agents_readback = world.resource::<AgentsReadback>().0.as_ref();
+            if let (true, Some(buffer)) = (readback.agents_requested, agents_readback) {
+                if buffer.copy_from(encoder, &agents_buf.storage) {
+                    readback.agents_requested = false;
+                    readback.agents_tick = world.resource::<SimulationClock>().tick;
+                }
+            }
+
+            let trail_image = world.resource::<RenderAssets<Image>>().get(&world.resource::<SlimeMoldImage>().0);
+            let trail_readback = world.resource::<TrailReadback>().0.as_ref().filter(|buffers| buffers.size == trail_buf.size);
+            if let (true, Some(trail_image), Some(buffers)) = (readback.trail_requested || readback.network_requested, trail_image, trail_readback) {
+                let size = Extent3d {
+                    width: trail_buf.size.width,
+                    height: trail_buf.size.height,
+                    depth_or_array_layers: 1,
+                };
+                if buffers.trail.copy_from_texture(encoder, &trail_image.texture, size, padded_bytes_per_row(trail_buf.size.width)) {
+                    buffers.reaction.copy_from(encoder, &trail_buf.reaction);
+                    readback.trail_recorded = Some(TrailExport {
+                        tick: world.resource::<SimulationClock>().tick,
+                        size: trail_buf.size,
+                        trail: Vec::new(),
+                        reaction: Vec::new(),
+                        export: readback.trail_requested,
+                        network: readback.network_requested,
+                    });
+                    readback.trail_requested = false;
+                    readback.network_requested = false;
+                }
+            }
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+fn dispatch_brush<'a>(
+    pass: &mut ComputePass<'a>,
+    brush: &BrushInput,
+    pipeline: &SlimeMoldPipeline,
+    pipeline_cache: &'a PipelineCache,
+    agents_buf: &'a SlimeMoldAgentsBuffer,
+    trail_buf: &SlimeMoldTrailBuffers,
+) {
+    if !brush.active() { return; }
+    let (texture_x, texture_y) = trail_buf.size.workgroups();
+
+    match (brush.tool, brush.strength > 0.0) {
+        (BrushTool::Trail, _) => {
+            let paint_trailmap_pipeline = pipeline_cache
+                .get_compute_pipeline(pipeline.paint_trailmap_pipeline)
+                .unwrap();
+            pass.set_pipeline(paint_trailmap_pipeline);
+            pass.dispatch_workgroups(texture_x, texture_y, 1);
+        }
+        (BrushTool::Agents, true) => {
+            let spawn_agents_pipeline = pipeline_cache
+                .get_compute_pipeline(pipeline.spawn_agents_pipeline)
+                .unwrap();
+            let (x, y) = agent_workgroups(brush.spawn_count);
+            pass.set_pipeline(spawn_agents_pipeline);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        (BrushTool::Agents, false) => {
+            let erase_agents_pipeline = pipeline_cache
+                .get_compute_pipeline(pipeline.erase_agents_pipeline)
+                .unwrap();
+            pass.set_pipeline(erase_agents_pipeline);
+            pass.dispatch_workgroups_indirect(&agents_buf.dispatch, 0);
+        }
+    }
+}
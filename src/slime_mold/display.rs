@@ -0,0 +1,61 @@
+use bevy::{prelude::*, core_pipeline::{tonemapping::Tonemapping, bloom::{BloomSettings, BloomPrefilterSettings}}, render::view::ColorGrading};
+
+use super::ui::UISettings;
+
+
+pub const TONEMAPPING_OPERATORS: [Tonemapping; 8] = [
+    Tonemapping::None,
+    Tonemapping::Reinhard,
+    Tonemapping::ReinhardLuminance,
+    Tonemapping::AcesFitted,
+    Tonemapping::AgX,
+    Tonemapping::SomewhatBoringDisplayTransform,
+    Tonemapping::TonyMcMapface,
+    Tonemapping::BlenderFilmic,
+];
+
+/// Applies the display settings to the post processing of the cameras, which run on the high dynamic range display image.
+pub fn update_post_processing(
+    mut commands: Commands,
+    settings: Res<UISettings>,
+    mut camera_query: Query<(Entity, &mut Tonemapping, Option<&mut ColorGrading>, Option<&mut BloomSettings>), With<Camera>>,
+) {
+    for (entity, mut tonemapping, color_grading, bloom) in &mut camera_query {
+        tonemapping.set_if_neq(settings.tonemapping);
+
+        match color_grading {
+            Some(mut color_grading) => {
+                color_grading.exposure = settings.exposure;
+                color_grading.gamma = settings.gamma;
+            }
+            None => {
+                commands.entity(entity).insert(ColorGrading {
+                    exposure: settings.exposure,
+                    gamma: settings.gamma,
+                    ..default()
+                });
+            }
+        }
+
+        match (bloom, settings.bloom_intensity > 0.0) {
+            (Some(mut bloom), true) => {
+                bloom.intensity = settings.bloom_intensity;
+                bloom.prefilter_settings.threshold = settings.bloom_threshold;
+            }
+            (None, true) => {
+                commands.entity(entity).insert(BloomSettings {
+                    intensity: settings.bloom_intensity,
+                    prefilter_settings: BloomPrefilterSettings {
+                        threshold: settings.bloom_threshold,
+                        threshold_softness: 0.2,
+                    },
+                    ..default()
+                });
+            }
+            (Some(_), false) => {
+                commands.entity(entity).remove::<BloomSettings>();
+            }
+            (None, false) => {}
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fs::{self, File}, io::{self, Write, BufWriter}, path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+
+use super::{VOLUME_SIZE, readback::{SimulationReadback, TrailExport}, buffers::Agent, ui::UISettings};
+
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AgentExportFormat {
+    #[default]
+    Csv,
+    /// A `float32` array with one row of the agent columns per living agent.
+    Npy,
+}
+
+impl AgentExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AgentExportFormat::Csv => "csv",
+            AgentExportFormat::Npy => "npy",
+        }
+    }
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TrailExportFormat {
+    #[default]
+    Npy,
+    /// Headerless little endian `f32` values, with the shape in the file name.
+    Raw,
+}
+
+/// Slot index, position in texels, heading in radians, age in seconds and time spent stuck in seconds.
+const AGENT_COLUMNS: [&str; 6] = ["slot", "x", "y", "heading", "age", "stuck"];
+
+
+/// Writes little endian `f32` data as a NumPy `.npy` file with the given shape, in row major order.
+pub fn write_npy(path: impl AsRef<Path>, shape: &[usize], data: &[u8]) -> io::Result<()> {
+    let shape = match shape {
+        [length] => format!("({},)", length),
+        _ => format!("({})", shape.iter().map(|dim| dim.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+    // The magic string, version and header length take 10 bytes, and the header ends in a newline on a 64 byte boundary.
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.extend(std::iter::repeat(' ').take(padding));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+/// A file name in the working directory that is unique to the current second.
+pub fn export_path(name: &str, extension: &str) -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    format!("{}_{}.{}", name, seconds, extension)
+}
+
+/// The living agents in read back agent slots, along with their slot index.
+fn living_agents(data: &[u8]) -> impl Iterator<Item = (usize, Agent)> + '_ {
+    data.chunks_exact(std::mem::size_of::<Agent>())
+        .map(bytemuck::pod_read_unaligned::<Agent>)
+        .enumerate()
+        .filter(|(_, agent)| agent.alive != 0)
+}
+
+pub fn write_agents_csv(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "{}", AGENT_COLUMNS.join(","))?;
+    for (slot, agent) in living_agents(data) {
+        writeln!(file, "{},{},{},{},{},{}", slot, agent.position[0], agent.position[1], agent.angle, agent.age, agent.stuck)?;
+    }
+    file.flush()
+}
+
+pub fn write_agents_npy(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let values: Vec<f32> = living_agents(data)
+        .flat_map(|(slot, agent)| [slot as f32, agent.position[0], agent.position[1], agent.angle, agent.age, agent.stuck])
+        .collect();
+    write_npy(path, &[values.len() / AGENT_COLUMNS.len(), AGENT_COLUMNS.len()], bytemuck::cast_slice(&values))
+}
+
+/// Writes the trail map as a `(height, width)` array, and the reaction-diffusion layer as a `(height, width, 2)` array when it runs.
+pub fn write_trail_export(export: &TrailExport, format: TrailExportFormat, reaction_diffusion: bool) -> io::Result<Vec<String>> {
+    let (width, height) = (export.size.width as usize, export.size.height as usize);
+    let mut layers = vec![("trail", &export.trail, vec![height, width])];
+    if reaction_diffusion {
+        layers.push(("reaction", &export.reaction, vec![height, width, 2]));
+    }
+
+    let mut paths = Vec::new();
+    for (name, data, shape) in layers {
+        let path = match format {
+            TrailExportFormat::Npy => {
+                let path = export_path(&format!("{}_{}", name, export.tick), "npy");
+                write_npy(&path, &shape, data)?;
+                path
+            }
+            TrailExportFormat::Raw => {
+                // Listed from the fastest changing axis, so a 2560x1440 trail map is named as such.
+                let dims = shape.iter().rev().map(|dim| dim.to_string()).collect::<Vec<_>>().join("x");
+                let path = export_path(&format!("{}_{}_{}", name, export.tick, dims), "f32");
+                fs::write(&path, data)?;
+                path
+            }
+        };
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Requests an agent export every `agent_export_interval` ticks.
+pub fn request_agent_exports(
+    settings: Res<UISettings>,
+    readback: Res<SimulationReadback>,
+    mut last_tick: Local<u64>,
+) {
+    let mut readback = readback.lock().unwrap();
+    let interval = settings.agent_export_interval as u64;
+    if interval > 0 && readback.tick / interval > *last_tick / interval {
+        readback.agents_requested = true;
+    }
+    *last_tick = readback.tick;
+}
+
+/// Writes data read back for export to disk, off the main thread.
+pub fn save_exports(readback: Res<SimulationReadback>, settings: Res<UISettings>) {
+    let mut readback = readback.lock().unwrap();
+
+    if let Some(volume) = readback.volume.take() {
+        IoTaskPool::get().spawn(async move {
+            let path = export_path("volume", "npy");
+            let size = VOLUME_SIZE as usize;
+            match write_npy(&path, &[size, size, size], &volume) {
+                Ok(()) => info!("Exported volume to {}", path),
+                Err(err) => error!("Failed to export volume to {}: {}", path, err),
+            }
+        }).detach();
+    }
+
+    if let Some((tick, agents)) = readback.agents.take() {
+        let format = settings.agent_export_format;
+        IoTaskPool::get().spawn(async move {
+            // Periodic exports can be less than a second apart, so the tick keeps their names apart.
+            let path = export_path(&format!("agents_{}", tick), format.extension());
+            let result = match format {
+                AgentExportFormat::Csv => write_agents_csv(&path, &agents),
+                AgentExportFormat::Npy => write_agents_npy(&path, &agents),
+            };
+            match result {
+                Ok(()) => info!("Exported agents to {}", path),
+                Err(err) => error!("Failed to export agents to {}: {}", path, err),
+            }
+        }).detach();
+    }
+
+    // A trail map also requested for network extraction is left until the extraction has taken its copy.
+    if readback.trail.as_ref().map_or(false, |trail| !trail.network) {
+        let trail = readback.trail.take().unwrap();
+        let format = settings.trail_export_format;
+        let reaction_diffusion = settings.reaction_diffusion;
+        IoTaskPool::get().spawn(async move {
+            match write_trail_export(&trail, format, reaction_diffusion) {
+                Ok(paths) => info!("Exported trail map to {}", paths.join(", ")),
+                Err(err) => error!("Failed to export trail map: {}", err),
+            }
+        }).detach();
+    }
+}
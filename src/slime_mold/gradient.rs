@@ -0,0 +1,211 @@
+use std::{fs, io};
+
+use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{Extent3d, TextureDimension, TextureFormat}}};
+
+use super::ui::UISettings;
+
+
+/// Width of the lookup texture the gradient is baked into.
+pub const GRADIENT_RESOLUTION: u32 = 256;
+/// File in the working directory that saved gradient presets are kept in.
+pub const GRADIENT_PRESETS_PATH: &str = "gradients.txt";
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Palette {
+    #[default]
+    Grayscale,
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 5] = [
+        Palette::Grayscale,
+        Palette::Viridis,
+        Palette::Magma,
+        Palette::Inferno,
+        Palette::Plasma,
+    ];
+
+    /// Evenly spaced sRGB colors of the palette.
+    fn colors(&self) -> &'static [&'static str] {
+        match self {
+            Palette::Grayscale => &["000000", "ffffff"],
+            Palette::Viridis => &["440154", "472d7b", "3b528b", "2c728e", "21918c", "28ae80", "5ec962", "addc30", "fde725"],
+            Palette::Magma => &["000004", "1c1044", "4f127b", "812581", "b5367a", "e55064", "fb8761", "fec287", "fcfdbf"],
+            Palette::Inferno => &["000004", "1f0c48", "550f6d", "88226a", "ba3655", "e35933", "f98e09", "f8c932", "fcffa4"],
+            Palette::Plasma => &["0d0887", "4c02a1", "7e03a8", "a92395", "cc4778", "e56b5d", "f89540", "fdc527", "f0f921"],
+        }
+    }
+
+    pub fn gradient(&self) -> ColorGradient {
+        let colors = self.colors();
+        let stops = colors
+            .iter()
+            .enumerate()
+            .map(|(i, hex)| ColorStop {
+                position: i as f32 / (colors.len() - 1) as f32,
+                color: Color::hex(hex).unwrap(),
+            })
+            .collect();
+        ColorGradient { stops }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ColorStop {
+    /// Trail intensity the color is shown at, from zero to one.
+    pub position: f32,
+    pub color: Color,
+}
+
+/// Maps trail intensity to a display color, interpolating linearly between stops.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ColorGradient {
+    pub stops: Vec<ColorStop>,
+}
+
+impl Default for ColorGradient {
+    fn default() -> Self {
+        Palette::default().gradient()
+    }
+}
+
+impl ColorGradient {
+    pub fn sort(&mut self) {
+        self.stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+    }
+
+    /// Linear color of the gradient at `t`, assuming the stops are sorted.
+    pub fn evaluate(&self, t: f32) -> Vec4 {
+        let linear = |stop: &ColorStop| Vec4::from(stop.color.as_linear_rgba_f32());
+
+        let Some(first) = self.stops.first() else { return Vec4::ZERO };
+        if t <= first.position { return linear(first); }
+
+        for pair in self.stops.windows(2) {
+            if t <= pair[1].position {
+                let span = pair[1].position - pair[0].position;
+                let s = if span > 0.0 { (t - pair[0].position) / span } else { 1.0 };
+                return linear(&pair[0]).lerp(linear(&pair[1]), s);
+            }
+        }
+        linear(self.stops.last().unwrap())
+    }
+
+    /// Serializes the gradient as `position r g b` entries separated by `|`, with linear colors.
+    fn to_line(&self) -> String {
+        self.stops
+            .iter()
+            .map(|stop| {
+                let [r, g, b, _] = stop.color.as_linear_rgba_f32();
+                format!("{} {} {} {}", stop.position, r, g, b)
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let stops = line
+            .split('|')
+            .map(|entry| {
+                let values = entry
+                    .split_whitespace()
+                    .map(|value| value.parse::<f32>().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                match values[..] {
+                    [position, r, g, b] => Some(ColorStop { position, color: Color::rgb_linear(r, g, b) }),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut gradient = Self { stops };
+        gradient.sort();
+        Some(gradient)
+    }
+}
+
+
+/// Named gradients saved from the settings window.
+#[derive(Resource, Default)]
+pub struct GradientPresets(pub Vec<(String, ColorGradient)>);
+
+impl GradientPresets {
+    /// Reads presets saved as `name: stops` lines, skipping lines that fail to parse.
+    pub fn load() -> Self {
+        let presets = fs::read_to_string(GRADIENT_PRESETS_PATH)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (name, stops) = line.split_once(':')?;
+                Some((name.trim().to_string(), ColorGradient::from_line(stops)?))
+            })
+            .collect();
+        Self(presets)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let contents = self.0
+            .iter()
+            .map(|(name, gradient)| format!("{}: {}\n", name, gradient.to_line()))
+            .collect::<String>();
+        fs::write(GRADIENT_PRESETS_PATH, contents)
+    }
+
+    /// Adds a preset, replacing any preset with the same name.
+    pub fn insert(&mut self, name: String, gradient: ColorGradient) {
+        match self.0.iter_mut().find(|(preset, _)| *preset == name) {
+            Some((_, preset)) => *preset = gradient,
+            None => self.0.push((name, gradient)),
+        }
+    }
+}
+
+
+/// The gradient baked into a lookup texture that the display passes sample.
+#[derive(Resource, Clone, Deref, ExtractResource)]
+pub struct GradientImage(pub Handle<Image>);
+
+pub fn setup_gradient(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let image = Image::new_fill(
+        Extent3d {
+            width: GRADIENT_RESOLUTION,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+
+    commands.insert_resource(GradientImage(images.add(image)));
+    commands.insert_resource(GradientPresets::load());
+}
+
+/// Rebakes the lookup texture whenever the gradient is edited.
+pub fn update_gradient_image(
+    settings: Res<UISettings>,
+    gradient_image: Res<GradientImage>,
+    mut images: ResMut<Assets<Image>>,
+    mut baked: Local<Option<ColorGradient>>,
+) {
+    if baked.as_ref() == Some(&settings.gradient) { return; }
+    let Some(image) = images.get_mut(&gradient_image.0) else { return };
+
+    let mut gradient = settings.gradient.clone();
+    gradient.sort();
+    image.data = (0..GRADIENT_RESOLUTION)
+        .flat_map(|i| {
+            let color = gradient.evaluate(i as f32 / (GRADIENT_RESOLUTION - 1) as f32);
+            Color::rgb_linear(color.x, color.y, color.z).as_rgba_u8()
+        })
+        .collect();
+
+    *baked = Some(settings.gradient.clone());
+}
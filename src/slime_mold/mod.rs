@@ -1,19 +1,41 @@
-use bevy::{prelude::*, render::{extract_resource::ExtractResourcePlugin, RenderApp, Render, render_graph::RenderGraph, RenderSet}};
+use bevy::{prelude::*, render::{extract_resource::ExtractResourcePlugin, RenderApp, Render, render_graph::RenderGraph, RenderSet, render_asset::PrepareAssetSet}};
 
-use self::{texture::{SlimeMoldImage, setup_texture}, buffers::{SettingsBuffer, extract_time, prepare_settings_buffer, SlimeMoldAgentsBuffer, extract_ui_settings}, compute::{queue_bind_group, SlimeMoldNode, SlimeMoldPipeline}, ui::UISettings};
+use self::{texture::{SlimeMoldImage, DisplayImage, SimulationSize, setup_texture, update_surface_display, update_sprite_size, update_simulation_size}, buffers::{SettingsBuffer, SimulationClock, extract_time, prepare_settings_buffer, prepare_trail_buffers, SlimeMoldAgentsBuffer, SlimeMoldTrailBuffers, extract_ui_settings}, compute::{queue_bind_group, SlimeMoldNode, SlimeMoldPipeline}, ui::UISettings, brush::{BrushInput, update_brush}, readback::{SimulationReadback, AgentsReadback, TrailReadback, readback_agent_counters, prepare_agents_readback, readback_agents, prepare_trail_readback, readback_trail, readback_stats}, parameter_maps::{ParameterMapImages, FlowMapImage, linearize_map_images}, volume::{VolumeImage, VolumeResources, VolumeSettingsBuffer, VolumePipeline, VolumeNode, setup_volume, update_display_image, prepare_volume_buffers, prepare_volume_settings, queue_volume_bind_group, readback_volume}, export::{save_exports, request_agent_exports}, gradient::{GradientImage, setup_gradient, update_gradient_image}, display::update_post_processing, overlay::{OverlaySettingsBuffer, OverlayPipeline, AgentOverlayNode, prepare_overlay_settings, queue_overlay_bind_group}, camera::{DisplaySampler, DisplaySamplers, update_camera, update_display_sampler, prepare_display_sampler}, checkpoint::{CheckpointLoad, CheckpointReadback, apply_command_line, load_checkpoint, autosave_checkpoint, save_checkpoints, prepare_checkpoint_readback, readback_checkpoint}, network::{NetworkAnalysis, update_network, draw_network}, statistics::{StatisticsHistory, update_statistics}};
 
 pub mod compute;
 pub mod texture;
 pub mod buffers;
 pub mod ui;
+pub mod brush;
+pub mod readback;
+pub mod parameter_maps;
+pub mod volume;
+pub mod export;
+pub mod gradient;
+pub mod display;
+pub mod overlay;
+pub mod camera;
+pub mod checkpoint;
+pub mod network;
+pub mod statistics;
 
 
+/// Default size of the trail map, used unless it is matched to the window.
 pub const TEXTURE_SIZE: (u32, u32) = (2560, 1440);
+/// Largest trail map matched to the window, which keeps the reaction-diffusion buffer under the default 128 MiB storage binding limit.
+pub const MAX_TEXELS: u32 = 2 * TEXTURE_SIZE.0 * TEXTURE_SIZE.1;
 pub const NUM_AGENTS: u32 = 1_000_000;
+pub const MAX_AGENTS: u32 = 2_000_000;
 pub const TEX_WORKGROUP_SIZE: u32 = 8;
 pub const AGENTS_WORKGROUP_SIZE: u32 = 16;
+pub const AGENTS_DISPATCH_WIDTH: u32 = 4096;
 pub const INITIAL_STATE: &str = "initAgentsInwardRing";
 
+pub const VOLUME_SIZE: u32 = 256;
+pub const VOLUME_AGENTS: u32 = 500_000;
+pub const VOLUME_DISPLAY_SIZE: (u32, u32) = (1280, 720);
+pub const VOLUME_WORKGROUP_SIZE: u32 = 4;
+
 
 #[derive(States, Default, Debug, Hash, Eq, PartialEq, Clone)]
 pub enum SimulationState {
@@ -23,28 +45,77 @@ pub enum SimulationState {
 }
 
 
+/// Workgroup counts for a pass over `count` agents, split into rows of `AGENTS_DISPATCH_WIDTH` workgroups.
+pub fn agent_workgroups(count: u32) -> (u32, u32) {
+    let workgroups = ((count + AGENTS_WORKGROUP_SIZE - 1) / AGENTS_WORKGROUP_SIZE).max(1);
+    let width = workgroups.min(AGENTS_DISPATCH_WIDTH);
+    (width, (workgroups + width - 1) / width)
+}
+
+
 pub struct SlimeMoldComputePlugin;
 
 impl Plugin for SlimeMoldComputePlugin {
     fn build(&self, app: &mut App) {
         app.add_state::<SimulationState>();
-        app.add_systems(Startup, setup_texture);
+        app.init_resource::<BrushInput>();
+        app.init_resource::<ParameterMapImages>();
+        app.init_resource::<FlowMapImage>();
+        app.init_resource::<SimulationSize>();
+        app.init_resource::<DisplaySampler>();
+        app.init_resource::<CheckpointLoad>();
+        app.init_resource::<NetworkAnalysis>();
+        app.init_resource::<StatisticsHistory>();
+        app.add_systems(Startup, (setup_texture, setup_volume, setup_gradient, apply_command_line));
+        app.add_systems(Update, (update_brush, linearize_map_images, update_simulation_size, update_display_image, update_surface_display, update_sprite_size, update_gradient_image, update_post_processing, update_camera, update_display_sampler, save_exports));
+        app.add_systems(Update, (load_checkpoint, autosave_checkpoint, save_checkpoints, request_agent_exports, update_network.before(save_exports), draw_network, update_statistics));
         app.add_plugins(ExtractResourcePlugin::<SlimeMoldImage>::default());
+        app.add_plugins(ExtractResourcePlugin::<DisplayImage>::default());
+        app.add_plugins(ExtractResourcePlugin::<SimulationSize>::default());
+        app.add_plugins(ExtractResourcePlugin::<DisplaySampler>::default());
+        app.add_plugins(ExtractResourcePlugin::<BrushInput>::default());
+        app.add_plugins(ExtractResourcePlugin::<ParameterMapImages>::default());
+        app.add_plugins(ExtractResourcePlugin::<FlowMapImage>::default());
+        app.add_plugins(ExtractResourcePlugin::<VolumeImage>::default());
+        app.add_plugins(ExtractResourcePlugin::<GradientImage>::default());
+
+        let readback = SimulationReadback::default();
+        app.insert_resource(readback.clone());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
+            .insert_resource(readback)
             .init_resource::<SettingsBuffer>()
+            .init_resource::<VolumeSettingsBuffer>()
+            .init_resource::<OverlaySettingsBuffer>()
             .init_resource::<Time>()
             .init_resource::<UISettings>()
+            .init_resource::<BrushInput>()
+            .init_resource::<ParameterMapImages>()
+            .init_resource::<FlowMapImage>()
+            .init_resource::<SimulationSize>()
+            .init_resource::<DisplaySampler>()
+            .init_resource::<SimulationClock>()
+            .init_resource::<CheckpointReadback>()
+            .init_resource::<AgentsReadback>()
+            .init_resource::<TrailReadback>()
             .add_state::<SimulationState>()
             .add_systems(ExtractSchedule, (extract_time, extract_ui_settings))
-            .add_systems(Render, prepare_settings_buffer.in_set(RenderSet::Prepare))
-            .add_systems(Render, queue_bind_group.in_set(RenderSet::Queue));
+            .add_systems(Render, (prepare_trail_buffers.before(prepare_settings_buffer), prepare_settings_buffer, prepare_volume_buffers, prepare_volume_settings, prepare_overlay_settings, prepare_checkpoint_readback.after(prepare_trail_buffers), prepare_agents_readback, prepare_trail_readback.after(prepare_trail_buffers), prepare_display_sampler.after(PrepareAssetSet::PreAssetPrepare)).in_set(RenderSet::Prepare))
+            .add_systems(Render, (queue_bind_group, queue_volume_bind_group, queue_overlay_bind_group).in_set(RenderSet::Queue))
+            .add_systems(Render, (readback_agent_counters, readback_agents, readback_trail, readback_volume, readback_checkpoint, readback_stats).in_set(RenderSet::Cleanup));
         
         let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
         render_graph.add_node("slime_mold", SlimeMoldNode::default());
+        render_graph.add_node("agent_overlay", AgentOverlayNode);
+        render_graph.add_node_edge("slime_mold", "agent_overlay");
+        render_graph.add_node_edge(
+            "agent_overlay",
+            bevy::render::main_graph::node::CAMERA_DRIVER,
+        );
+        render_graph.add_node("slime_mold_volume", VolumeNode::default());
         render_graph.add_node_edge(
-            "slime_mold",
+            "slime_mold_volume",
             bevy::render::main_graph::node::CAMERA_DRIVER,
         );
     }
@@ -52,6 +123,11 @@ impl Plugin for SlimeMoldComputePlugin {
     fn finish(&self, app: &mut App) {
         let render_app = app.sub_app_mut(RenderApp);
         render_app.init_resource::<SlimeMoldAgentsBuffer>();
+        render_app.init_resource::<SlimeMoldTrailBuffers>();
         render_app.init_resource::<SlimeMoldPipeline>();
+        render_app.init_resource::<VolumeResources>();
+        render_app.init_resource::<VolumePipeline>();
+        render_app.init_resource::<OverlayPipeline>();
+        render_app.init_resource::<DisplaySamplers>();
     }
 }
\ No newline at end of file
@@ -0,0 +1,741 @@
+use std::{collections::HashSet, fmt::Write as _, fs::File, io::{self, Write, BufWriter}, path::Path, sync::{Arc, Mutex}};
+
+use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, IoTaskPool}};
+
+use super::{texture::{SlimeMoldSprite, texture_to_world}, readback::{SimulationReadback, TrailExport}, ui::{UISettings, SimulationMode}, export::export_path};
+
+
+/// Neighbours of a pixel clockwise from the top, where y points down.
+const NEIGHBOURS: [(i32, i32); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+const NO_NODE: u32 = u32::MAX;
+/// Every this many skeleton pixels along an edge is kept as a point of its polyline.
+const POINT_SPACING: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct NetworkNode {
+    /// Position in texels.
+    pub position: Vec2,
+    pub degree: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct NetworkEdge {
+    pub from: usize,
+    pub to: usize,
+    /// Length along the skeleton in texels.
+    pub length: f32,
+    /// Polyline from `from` to `to` in texels.
+    pub points: Vec<Vec2>,
+}
+
+/// The transport network of the trail map: junctions and endpoints of its skeleton, connected by the branches between them.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkGraph {
+    pub tick: u64,
+    pub width: u32,
+    pub height: u32,
+    pub nodes: Vec<NetworkNode>,
+    pub edges: Vec<NetworkEdge>,
+}
+
+impl NetworkGraph {
+    pub fn total_length(&self) -> f32 {
+        self.edges.iter().map(|edge| edge.length).sum()
+    }
+
+    /// Thresholds the trail map, thins it to a one pixel wide skeleton and traces the skeleton into a graph.
+    /// Dangling branches shorter than `min_branch` are pruned.
+    pub fn extract(trail: &TrailExport, threshold: f32, min_branch: f32) -> Self {
+        let (width, height) = (trail.size.width, trail.size.height);
+        let values: Vec<f32> = bytemuck::pod_collect_to_vec(&trail.trail);
+        let mut skeleton = Skeleton {
+            width: width as i32,
+            height: height as i32,
+            mask: values.iter().map(|&value| value >= threshold).collect(),
+        };
+        skeleton.thin();
+
+        let mut builder = GraphBuilder::new(skeleton);
+        builder.trace();
+        let (nodes, edges) = builder.finish(min_branch);
+
+        Self {
+            tick: trail.tick,
+            width,
+            height,
+            nodes,
+            edges,
+        }
+    }
+}
+
+
+struct Skeleton {
+    width: i32,
+    height: i32,
+    mask: Vec<bool>,
+}
+
+impl Skeleton {
+    fn neighbour(&self, index: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+        let x = index as i32 % self.width + dx;
+        let y = index as i32 / self.width + dy;
+        (x >= 0 && y >= 0 && x < self.width && y < self.height).then(|| (y * self.width + x) as usize)
+    }
+
+    fn ring(&self, index: usize) -> [bool; 8] {
+        NEIGHBOURS.map(|offset| self.neighbour(index, offset).is_some_and(|neighbour| self.mask[neighbour]))
+    }
+
+    /// Number of background to foreground transitions around a pixel, two for a pixel in the middle of a line.
+    fn crossings(ring: &[bool; 8]) -> usize {
+        (0..8).filter(|&i| !ring[i] && ring[(i + 1) % 8]).count()
+    }
+
+    /// Zhang-Suen thinning, which peels pixels off the boundary of the mask until only its medial lines remain.
+    fn thin(&mut self) {
+        let mut pixels: Vec<usize> = (0..self.mask.len()).filter(|&index| self.mask[index]).collect();
+        loop {
+            let mut changed = false;
+            for step in 0..2 {
+                let removed: Vec<usize> = pixels
+                    .iter()
+                    .copied()
+                    .filter(|&index| {
+                        let ring = self.ring(index);
+                        let [n, _, e, _, s, _, w, _] = ring;
+                        let count = ring.iter().filter(|&&set| set).count();
+                        let sides = match step {
+                            0 => !(e && s && (n || w)),
+                            _ => !(n && w && (e || s)),
+                        };
+                        (2..=6).contains(&count) && Self::crossings(&ring) == 1 && sides
+                    })
+                    .collect();
+                for &index in &removed {
+                    self.mask[index] = false;
+                }
+                changed |= !removed.is_empty();
+            }
+            if !changed { break; }
+            pixels.retain(|&index| self.mask[index]);
+        }
+    }
+}
+
+
+struct RawEdge {
+    from: usize,
+    to: usize,
+    length: f32,
+    pixels: Vec<usize>,
+}
+
+impl RawEdge {
+    fn reversed(mut self) -> Self {
+        std::mem::swap(&mut self.from, &mut self.to);
+        self.pixels.reverse();
+        self
+    }
+}
+
+struct GraphBuilder {
+    skeleton: Skeleton,
+    /// Node of each node pixel, neighbouring junction pixels share a node.
+    node_ids: Vec<u32>,
+    /// Skeleton pixels already part of a traced edge.
+    visited: Vec<bool>,
+    node_positions: Vec<Vec2>,
+    edges: Vec<RawEdge>,
+}
+
+impl GraphBuilder {
+    fn new(skeleton: Skeleton) -> Self {
+        let len = skeleton.mask.len();
+        Self {
+            skeleton,
+            node_ids: vec![NO_NODE; len],
+            visited: vec![false; len],
+            node_positions: Vec::new(),
+            edges: Vec::new(),
+        }
+    }
+
+    fn texel(&self, index: usize) -> Vec2 {
+        let width = self.skeleton.width as usize;
+        Vec2::new((index % width) as f32 + 0.5, (index / width) as f32 + 0.5)
+    }
+
+    fn skeleton_neighbours(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        NEIGHBOURS
+            .iter()
+            .filter_map(move |&offset| self.skeleton.neighbour(index, offset))
+            .filter(|&neighbour| self.skeleton.mask[neighbour])
+    }
+
+    /// Turns a connected group of node pixels into a single node at their centroid.
+    fn add_node(&mut self, start: usize) {
+        let id = self.node_positions.len() as u32;
+        let mut sum = Vec2::ZERO;
+        let mut count = 0;
+        let mut stack = vec![start];
+        self.node_ids[start] = id;
+        while let Some(index) = stack.pop() {
+            sum += self.texel(index);
+            count += 1;
+            let neighbours: Vec<usize> = self.skeleton_neighbours(index).collect();
+            for neighbour in neighbours {
+                if self.node_ids[neighbour] == NO_NODE && self.is_node_pixel(neighbour) {
+                    self.node_ids[neighbour] = id;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        self.node_positions.push(sum / count as f32);
+    }
+
+    /// Endpoints and junctions, where the skeleton does not simply pass through.
+    fn is_node_pixel(&self, index: usize) -> bool {
+        Skeleton::crossings(&self.skeleton.ring(index)) != 2
+    }
+
+    fn step_length(&self, from: usize, to: usize) -> f32 {
+        let width = self.skeleton.width as usize;
+        if from % width != to % width && from / width != to / width { std::f32::consts::SQRT_2 } else { 1.0 }
+    }
+
+    fn trace(&mut self) {
+        for index in 0..self.skeleton.mask.len() {
+            if self.skeleton.mask[index] && self.node_ids[index] == NO_NODE && self.is_node_pixel(index) {
+                self.add_node(index);
+            }
+        }
+
+        let mut direct = HashSet::new();
+        for index in 0..self.skeleton.mask.len() {
+            if self.node_ids[index] != NO_NODE {
+                self.trace_from(index, &mut direct);
+            }
+        }
+
+        // What is left are closed loops without any junction, which get a node to start and end at.
+        for index in 0..self.skeleton.mask.len() {
+            if self.skeleton.mask[index] && self.node_ids[index] == NO_NODE && !self.visited[index] {
+                self.node_ids[index] = self.node_positions.len() as u32;
+                self.node_positions.push(self.texel(index));
+                self.trace_from(index, &mut direct);
+            }
+        }
+    }
+
+    /// Traces every untraced edge leaving a node pixel.
+    fn trace_from(&mut self, index: usize, direct: &mut HashSet<(u32, u32)>) {
+        let start = self.node_ids[index];
+        let neighbours: Vec<usize> = self.skeleton_neighbours(index).collect();
+        for neighbour in neighbours {
+            let other = self.node_ids[neighbour];
+            if other == NO_NODE {
+                if !self.visited[neighbour] {
+                    self.visited[neighbour] = true;
+                    self.trace_edge(index, neighbour);
+                }
+            } else if other != start && direct.insert((start.min(other), start.max(other))) {
+                self.edges.push(RawEdge {
+                    from: start as usize,
+                    to: other as usize,
+                    length: self.step_length(index, neighbour),
+                    pixels: vec![index, neighbour],
+                });
+            }
+        }
+    }
+
+    /// Walks along the skeleton from a node pixel until it reaches another node pixel.
+    fn trace_edge(&mut self, start: usize, first: usize) {
+        let start_node = self.node_ids[start];
+        let mut pixels = vec![start, first];
+        let mut length = self.step_length(start, first);
+        let mut current = first;
+
+        let end_node = loop {
+            // The start node is only a valid end once the edge has left it, which makes a loop.
+            let end = self.skeleton_neighbours(current).find(|&neighbour| {
+                let node = self.node_ids[neighbour];
+                node != NO_NODE && neighbour != pixels[pixels.len() - 2] && (node != start_node || pixels.len() > 4)
+            });
+            if let Some(end) = end {
+                length += self.step_length(current, end);
+                pixels.push(end);
+                break self.node_ids[end];
+            }
+
+            // Orthogonal steps are taken first, so staircases are followed pixel by pixel instead of being cut short.
+            let next = self
+                .skeleton_neighbours(current)
+                .filter(|&neighbour| self.node_ids[neighbour] == NO_NODE && !self.visited[neighbour])
+                .min_by(|&a, &b| self.step_length(current, a).total_cmp(&self.step_length(current, b)));
+            match next {
+                Some(next) => {
+                    self.visited[next] = true;
+                    length += self.step_length(current, next);
+                    pixels.push(next);
+                    current = next;
+                }
+                None => {
+                    // A dead end the crossing number did not catch becomes an endpoint.
+                    let id = self.node_positions.len() as u32;
+                    self.node_ids[current] = id;
+                    self.node_positions.push(self.texel(current));
+                    break id;
+                }
+            }
+        };
+
+        self.edges.push(RawEdge {
+            from: start_node as usize,
+            to: end_node as usize,
+            length,
+            pixels,
+        });
+    }
+
+    /// Prunes short dangling branches, joins the edges on either side of nodes left with two of them, and drops unconnected nodes.
+    fn finish(self, min_branch: f32) -> (Vec<NetworkNode>, Vec<NetworkEdge>) {
+        let node_count = self.node_positions.len();
+        let mut degrees = vec![0u32; node_count];
+        for edge in &self.edges {
+            degrees[edge.from] += 1;
+            degrees[edge.to] += 1;
+        }
+
+        let mut edges: Vec<Option<RawEdge>> = self.edges
+            .into_iter()
+            .map(|edge| {
+                let dangling = degrees[edge.from] == 1 || degrees[edge.to] == 1;
+                (!dangling || edge.length >= min_branch).then_some(edge)
+            })
+            .collect();
+
+        let mut incident = vec![Vec::new(); node_count];
+        for (i, edge) in edges.iter().enumerate() {
+            if let Some(edge) = edge {
+                incident[edge.from].push(i);
+                incident[edge.to].push(i);
+            }
+        }
+
+        for node in 0..node_count {
+            let [a, b] = incident[node][..] else { continue };
+            if a == b { continue; }
+            let first = edges[a].take().unwrap();
+            let first = if first.to == node { first } else { first.reversed() };
+            let second = edges[b].take().unwrap();
+            let second = if second.from == node { second } else { second.reversed() };
+
+            for id in incident[second.to].iter_mut() {
+                if *id == b {
+                    *id = a;
+                }
+            }
+            let mut pixels = first.pixels;
+            pixels.extend_from_slice(&second.pixels[1..]);
+            edges[a] = Some(RawEdge {
+                from: first.from,
+                to: second.to,
+                length: first.length + second.length,
+                pixels,
+            });
+            incident[node].clear();
+        }
+
+        let mut remap = vec![usize::MAX; node_count];
+        let mut nodes = Vec::new();
+        for (node, edge_ids) in incident.iter().enumerate() {
+            if edge_ids.is_empty() { continue; }
+            remap[node] = nodes.len();
+            nodes.push(NetworkNode {
+                position: self.node_positions[node],
+                degree: edge_ids.len() as u32,
+            });
+        }
+
+        let width = self.skeleton.width as usize;
+        let texel = |index: usize| Vec2::new((index % width) as f32 + 0.5, (index / width) as f32 + 0.5);
+        let edges = edges
+            .into_iter()
+            .flatten()
+            .map(|edge| {
+                let last = edge.pixels.len() - 1;
+                NetworkEdge {
+                    from: remap[edge.from],
+                    to: remap[edge.to],
+                    length: edge.length,
+                    points: edge.pixels
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| i % POINT_SPACING == 0 || *i == last)
+                        .map(|(_, &index)| texel(index))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        (nodes, edges)
+    }
+}
+
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NetworkExportFormat {
+    GraphMl,
+    Json,
+}
+
+/// Formats a float for a GraphML `float` attribute, which spells non-finite values as XML Schema does.
+fn xml_float(value: f32) -> String {
+    match value {
+        value if value.is_nan() => "NaN".to_string(),
+        value if value.is_infinite() => if value > 0.0 { "INF" } else { "-INF" }.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Formats a float as a JSON number, JSON has none for non-finite values so they become `null`.
+fn json_float(value: f32) -> String {
+    if value.is_finite() { value.to_string() } else { "null".to_string() }
+}
+
+fn graphml_text(graph: &NetworkGraph) -> String {
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(xml, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#);
+    let _ = writeln!(xml, r#"  <key id="x" for="node" attr.name="x" attr.type="float"/>"#);
+    let _ = writeln!(xml, r#"  <key id="y" for="node" attr.name="y" attr.type="float"/>"#);
+    let _ = writeln!(xml, r#"  <key id="degree" for="node" attr.name="degree" attr.type="int"/>"#);
+    let _ = writeln!(xml, r#"  <key id="length" for="edge" attr.name="length" attr.type="float"/>"#);
+    let _ = writeln!(xml, r#"  <graph id="network" edgedefault="undirected">"#);
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let _ = writeln!(
+            xml,
+            r#"    <node id="n{}"><data key="x">{}</data><data key="y">{}</data><data key="degree">{}</data></node>"#,
+            i, xml_float(node.position.x), xml_float(node.position.y), node.degree,
+        );
+    }
+    for edge in &graph.edges {
+        let _ = writeln!(xml, r#"    <edge source="n{}" target="n{}"><data key="length">{}</data></edge>"#, edge.from, edge.to, xml_float(edge.length));
+    }
+    xml.push_str("  </graph>\n");
+    xml.push_str("</graphml>\n");
+    xml
+}
+
+fn json_text(graph: &NetworkGraph) -> String {
+    let mut json = String::new();
+    let _ = write!(json, r#"{{"tick":{},"width":{},"height":{},"nodes":["#, graph.tick, graph.width, graph.height);
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let _ = write!(
+            json,
+            r#"{}{{"id":{},"x":{},"y":{},"degree":{}}}"#,
+            separator, i, json_float(node.position.x), json_float(node.position.y), node.degree,
+        );
+    }
+    json.push_str(r#"],"edges":["#);
+    for (i, edge) in graph.edges.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let points = edge.points.iter().map(|point| format!("[{},{}]", json_float(point.x), json_float(point.y))).collect::<Vec<_>>().join(",");
+        let _ = write!(
+            json,
+            r#"{}{{"source":{},"target":{},"length":{},"points":[{}]}}"#,
+            separator, edge.from, edge.to, json_float(edge.length), points,
+        );
+    }
+    json.push_str("]}\n");
+    json
+}
+
+pub fn write_graphml(path: impl AsRef<Path>, graph: &NetworkGraph) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(graphml_text(graph).as_bytes())?;
+    file.flush()
+}
+
+/// Writes the graph as JSON, including the polyline of every edge.
+pub fn write_json(path: impl AsRef<Path>, graph: &NetworkGraph) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(json_text(graph).as_bytes())?;
+    file.flush()
+}
+
+/// Writes the graph to disk, off the main thread.
+pub fn export_network(graph: &NetworkGraph, format: NetworkExportFormat) {
+    let graph = graph.clone();
+    IoTaskPool::get().spawn(async move {
+        let name = format!("network_{}", graph.tick);
+        let (path, result) = match format {
+            NetworkExportFormat::GraphMl => {
+                let path = export_path(&name, "graphml");
+                let result = write_graphml(&path, &graph);
+                (path, result)
+            }
+            NetworkExportFormat::Json => {
+                let path = export_path(&name, "json");
+                let result = write_json(&path, &graph);
+                (path, result)
+            }
+        };
+        match result {
+            Ok(()) => info!("Exported network to {}", path),
+            Err(err) => error!("Failed to export network to {}: {}", path, err),
+        }
+    }).detach();
+}
+
+
+#[derive(Resource, Default)]
+pub struct NetworkAnalysis {
+    /// The most recently extracted network.
+    pub graph: Option<NetworkGraph>,
+    /// Set from the UI while a trail map is read back and analyzed.
+    pub extracting: bool,
+    result: Arc<Mutex<Option<NetworkGraph>>>,
+}
+
+/// Hands trail maps read back for analysis to a background task, and picks up the finished graphs.
+pub fn update_network(
+    mut network: ResMut<NetworkAnalysis>,
+    readback: Res<SimulationReadback>,
+    settings: Res<UISettings>,
+) {
+    let finished = network.result.lock().unwrap().take();
+    if let Some(graph) = finished {
+        info!("Extracted a network of {} nodes and {} edges", graph.nodes.len(), graph.edges.len());
+        network.graph = Some(graph);
+        network.extracting = false;
+    }
+
+    let mut readback = readback.lock().unwrap();
+    let Some(trail) = readback.trail.as_mut().filter(|trail| trail.network) else { return };
+    trail.network = false;
+    // A trail map also requested for export is left for the export to pick up.
+    let trail = if trail.export { trail.clone() } else { readback.trail.take().unwrap() };
+    drop(readback);
+
+    let threshold = settings.network_threshold;
+    let min_branch = settings.network_min_branch;
+    let result = network.result.clone();
+    AsyncComputeTaskPool::get().spawn(async move {
+        let graph = NetworkGraph::extract(&trail, threshold, min_branch);
+        *result.lock().unwrap() = Some(graph);
+    }).detach();
+}
+
+/// Draws the extracted network over the trail map, with dots at junctions and endpoints.
+pub fn draw_network(
+    mut gizmos: Gizmos,
+    network: Res<NetworkAnalysis>,
+    settings: Res<UISettings>,
+    sprite_query: Query<(&Sprite, &GlobalTransform), With<SlimeMoldSprite>>,
+) {
+    if !settings.network_overlay || settings.mode != SimulationMode::Flat { return; }
+    let Some(graph) = &network.graph else { return };
+    let Ok((sprite, sprite_transform)) = sprite_query.get_single() else { return };
+    let Some(sprite_size) = sprite.custom_size else { return };
+
+    let texture_size = Vec2::new(graph.width as f32, graph.height as f32);
+    let to_world = |position: Vec2| texture_to_world(position, sprite_size, sprite_transform, texture_size);
+    let radius = 2.0 * sprite_size.x / texture_size.x;
+
+    for edge in &graph.edges {
+        gizmos.linestrip_2d(edge.points.iter().map(|&point| to_world(point)), settings.network_color);
+    }
+    for node in graph.nodes.iter().filter(|node| node.degree != 2) {
+        gizmos.circle_2d(to_world(node.position), radius, settings.network_color);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slime_mold::texture::SimulationSize;
+
+    /// A trail map of the given size that is 1 on the listed texels and 0 everywhere else.
+    fn trail(width: u32, height: u32, texels: impl IntoIterator<Item = (u32, u32)>) -> TrailExport {
+        let mut values = vec![0.0f32; (width * height) as usize];
+        for (x, y) in texels {
+            values[(y * width + x) as usize] = 1.0;
+        }
+        TrailExport {
+            tick: 7,
+            size: SimulationSize { width, height },
+            trail: bytemuck::cast_slice(&values).to_vec(),
+            reaction: Vec::new(),
+            export: false,
+            network: true,
+        }
+    }
+
+    /// A horizontal line along y = 5 from x = 2 to 22 with a stem going down from x = 12 to y = 5 + `stem`.
+    fn t_junction(stem: u32) -> TrailExport {
+        trail(25, 20, (2..=22).map(|x| (x, 5)).chain((6..=5 + stem).map(|y| (12, y))))
+    }
+
+    fn sorted_lengths(graph: &NetworkGraph) -> Vec<f32> {
+        let mut lengths: Vec<f32> = graph.edges.iter().map(|edge| edge.length).collect();
+        lengths.sort_by(f32::total_cmp);
+        lengths
+    }
+
+    #[test]
+    fn straight_line_is_one_edge_between_two_endpoints() {
+        let graph = NetworkGraph::extract(&trail(20, 7, (2..=17).map(|x| (x, 3))), 0.5, 0.0);
+        assert_eq!((graph.tick, graph.width, graph.height), (7, 20, 7));
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.nodes.iter().all(|node| node.degree == 1));
+        assert_eq!(graph.edges.len(), 1);
+
+        let edge = &graph.edges[0];
+        assert_eq!(edge.length, 15.0);
+        assert_eq!(edge.points.first(), Some(&Vec2::new(2.5, 3.5)));
+        assert_eq!(edge.points.last(), Some(&Vec2::new(17.5, 3.5)));
+        assert_eq!(graph.nodes[edge.from].position, Vec2::new(2.5, 3.5));
+        assert_eq!(graph.nodes[edge.to].position, Vec2::new(17.5, 3.5));
+    }
+
+    #[test]
+    fn t_junction_has_a_node_of_degree_three() {
+        let graph = NetworkGraph::extract(&t_junction(10), 0.5, 0.0);
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.edges.len(), 3);
+
+        let junction = graph.nodes.iter().position(|node| node.degree == 3).unwrap();
+        assert_eq!(graph.nodes[junction].position, Vec2::new(12.5, 5.5));
+        assert_eq!(graph.nodes.iter().filter(|node| node.degree == 1).count(), 3);
+        assert!(graph.edges.iter().all(|edge| edge.from == junction || edge.to == junction));
+        assert_eq!(sorted_lengths(&graph), vec![10.0, 10.0, 10.0]);
+        assert_eq!(graph.total_length(), 30.0);
+    }
+
+    #[test]
+    fn closed_loop_starts_and_ends_at_one_node() {
+        let outline = (3..=12).flat_map(|i| [(i, 3), (i, 12), (3, i), (12, i)]);
+        let graph = NetworkGraph::extract(&trail(16, 16, outline), 0.5, 0.0);
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!(graph.nodes[0].degree, 2);
+        assert_eq!(graph.edges.len(), 1);
+
+        let edge = &graph.edges[0];
+        assert_eq!((edge.from, edge.to), (0, 0));
+        assert_eq!(edge.length, 36.0);
+        assert_eq!(edge.points.first(), edge.points.last());
+    }
+
+    #[test]
+    fn spurs_shorter_than_min_branch_are_pruned() {
+        let graph = NetworkGraph::extract(&t_junction(3), 0.5, 0.0);
+        assert_eq!(sorted_lengths(&graph), vec![3.0, 10.0, 10.0]);
+
+        // Without the stem the junction is left with two edges, which are joined into one.
+        let graph = NetworkGraph::extract(&t_junction(3), 0.5, 5.0);
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.nodes.iter().all(|node| node.degree == 1));
+        assert_eq!(sorted_lengths(&graph), vec![20.0]);
+        let edge = &graph.edges[0];
+        let ends = [graph.nodes[edge.from].position, graph.nodes[edge.to].position];
+        assert!(ends.contains(&Vec2::new(2.5, 5.5)) && ends.contains(&Vec2::new(22.5, 5.5)));
+    }
+
+    fn test_graph() -> NetworkGraph {
+        NetworkGraph {
+            tick: 42,
+            width: 8,
+            height: 4,
+            nodes: vec![
+                NetworkNode { position: Vec2::new(0.5, 1.5), degree: 1 },
+                NetworkNode { position: Vec2::new(f32::NAN, 2.0), degree: 1 },
+            ],
+            edges: vec![NetworkEdge {
+                from: 0,
+                to: 1,
+                length: f32::INFINITY,
+                points: vec![Vec2::new(0.5, 1.5), Vec2::new(f32::NEG_INFINITY, 2.0)],
+            }],
+        }
+    }
+
+    /// Checks that every tag is closed in order and that no text or attribute holds a stray `<` or `&`.
+    fn assert_well_formed_xml(xml: &str) {
+        let body = xml.strip_prefix(r#"<?xml version="1.0" encoding="UTF-8"?>"#).expect("missing declaration");
+        let mut open = Vec::new();
+        let mut rest = body;
+        while let Some(start) = rest.find('<') {
+            assert!(!rest[..start].contains('&') && !rest[..start].contains('>'), "unescaped text in {:?}", &rest[..start]);
+            let end = rest[start..].find('>').expect("unterminated tag") + start;
+            let tag = &rest[start + 1..end];
+            assert!(!tag.contains('<') && !tag.contains('&'), "unescaped attribute in {:?}", tag);
+            assert_eq!(tag.matches('"').count() % 2, 0, "unbalanced quotes in {:?}", tag);
+            let name = tag.trim_start_matches('/').trim_end_matches('/').split_whitespace().next().unwrap();
+            if tag.starts_with('/') {
+                assert_eq!(open.pop(), Some(name), "mismatched closing tag");
+            } else if !tag.ends_with('/') {
+                open.push(name);
+            }
+            rest = &rest[end + 1..];
+        }
+        assert!(rest.trim().is_empty() && open.is_empty(), "unclosed tags {:?}", open);
+    }
+
+    /// A minimal JSON parser that only checks the syntax, returning the input left after one value.
+    fn parse_json_value(json: &str) -> &str {
+        let json = json.trim_start();
+        let (open, close) = match json.chars().next().expect("missing value") {
+            '{' => ('{', '}'),
+            '[' => ('[', ']'),
+            '"' => {
+                let end = json[1..].find('"').expect("unterminated string") + 1;
+                assert!(!json[1..end].contains('\\'), "unexpected escape");
+                return &json[end + 1..];
+            }
+            _ => {
+                let end = json.find(|c: char| matches!(c, ',' | ']' | '}') || c.is_whitespace()).unwrap_or(json.len());
+                let token = &json[..end];
+                assert!(token == "null" || token == "true" || token == "false" || token.parse::<f64>().is_ok_and(f64::is_finite), "invalid token {:?}", token);
+                return &json[end..];
+            }
+        };
+        let mut rest = json[1..].trim_start();
+        if let Some(after) = rest.strip_prefix(close) { return after; }
+        loop {
+            if open == '{' {
+                assert!(rest.starts_with('"'), "object key is not a string");
+                rest = parse_json_value(rest).trim_start().strip_prefix(':').expect("missing colon");
+            }
+            rest = parse_json_value(rest).trim_start();
+            match rest.chars().next() {
+                Some(',') => rest = &rest[1..],
+                Some(c) if c == close => return &rest[1..],
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn graphml_is_well_formed_with_non_finite_values() {
+        let xml = graphml_text(&test_graph());
+        assert_well_formed_xml(&xml);
+        assert!(xml.contains(r#"<node id="n1"><data key="x">NaN</data><data key="y">2</data>"#));
+        assert!(xml.contains(r#"<edge source="n0" target="n1"><data key="length">INF</data></edge>"#));
+        assert_eq!(xml.matches("<node ").count(), 2);
+        assert_eq!(xml.matches("<edge ").count(), 1);
+    }
+
+    #[test]
+    fn json_is_well_formed_with_non_finite_values() {
+        let json = json_text(&test_graph());
+        assert!(parse_json_value(&json).trim().is_empty());
+        assert!(json.starts_with(r#"{"tick":42,"width":8,"height":4,"nodes":[{"id":0,"x":0.5,"y":1.5,"degree":1},"#));
+        assert!(json.contains(r#"{"id":1,"x":null,"y":2,"degree":1}"#));
+        assert!(json.contains(r#"{"source":0,"target":1,"length":null,"points":[[0.5,1.5],[null,2]]}"#));
+    }
+}
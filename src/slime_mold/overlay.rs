@@ -0,0 +1,193 @@
+use bevy::{prelude::*, render::{render_resource::{ShaderType, UniformBuffer, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, BufferBindingType, BufferSize, CachedRenderPipelineId, PipelineCache, RenderPipelineDescriptor, VertexState, FragmentState, ColorTargetState, ColorWrites, BlendState, PrimitiveState, MultisampleState, TextureFormat, RenderPassDescriptor, RenderPassColorAttachment, Operations, LoadOp}, render_asset::RenderAssets, renderer::{RenderDevice, RenderQueue, RenderContext}, render_graph}};
+
+use super::{MAX_AGENTS, texture::{DisplayImage, SimulationSize}, buffers::{SlimeMoldAgentsBuffer, Agent}, ui::{UISettings, SimulationMode}, readback::SimulationReadback};
+
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OverlayStyle {
+    #[default]
+    Points = 0,
+    /// Short lines along the heading of each agent.
+    Headings = 1,
+}
+
+#[derive(Default, Clone, ShaderType)]
+pub struct OverlaySettingsUniform {
+    color: Vec4,
+    dims: Vec2,
+    size: f32,
+    fraction: f32,
+    style: u32,
+}
+
+#[derive(Resource, Default)]
+pub struct OverlaySettingsBuffer {
+    pub buffer: UniformBuffer<OverlaySettingsUniform>,
+}
+
+pub fn prepare_overlay_settings(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut settings_buffer: ResMut<OverlaySettingsBuffer>,
+    settings: Res<UISettings>,
+    simulation_size: Res<SimulationSize>,
+) {
+    let buffer = settings_buffer.buffer.get_mut();
+    buffer.color = Vec4::from(settings.overlay_color.as_linear_rgba_f32());
+    buffer.dims = simulation_size.as_vec2();
+    buffer.size = settings.overlay_size;
+    buffer.fraction = settings.overlay_fraction;
+    buffer.style = settings.overlay_style as u32;
+
+    settings_buffer.buffer.write_buffer(&device, &queue);
+}
+
+
+#[derive(Resource)]
+struct OverlayBindGroup(BindGroup);
+
+pub fn queue_overlay_bind_group(
+    mut commands: Commands,
+    pipeline: Res<OverlayPipeline>,
+    agents_buf: Res<SlimeMoldAgentsBuffer>,
+    overlay_settings: Res<OverlaySettingsBuffer>,
+    render_device: Res<RenderDevice>,
+) {
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: agents_buf.storage.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: overlay_settings.buffer.binding().unwrap(),
+            },
+        ],
+    });
+    commands.insert_resource(OverlayBindGroup(bind_group));
+}
+
+#[derive(Resource)]
+pub struct OverlayPipeline {
+    bind_group_layout: BindGroupLayout,
+    draw_agents_pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for OverlayPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let bind_group_layout =
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::VERTEX,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: true,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(MAX_AGENTS as u64 * std::mem::size_of::<Agent>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/agent_overlay.wgsl");
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let draw_agents_pipeline = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: None,
+            layout: vec![bind_group_layout.clone()],
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader,
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::Rgba16Float,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+        });
+
+        OverlayPipeline {
+            bind_group_layout,
+            draw_agents_pipeline,
+        }
+    }
+}
+
+/// Draws agents on top of the display image, with one instanced quad per drawn agent.
+#[derive(Default)]
+pub struct AgentOverlayNode;
+
+impl render_graph::Node for AgentOverlayNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let settings = world.resource::<UISettings>();
+        if !settings.agent_overlay || settings.mode == SimulationMode::Volume {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<OverlayPipeline>();
+        let Some(draw_agents_pipeline) = pipeline_cache.get_render_pipeline(pipeline.draw_agents_pipeline) else { return Ok(()) };
+        let Some(bind_group) = world.get_resource::<OverlayBindGroup>() else { return Ok(()) };
+        let Some(display_image) = world.resource::<RenderAssets<Image>>().get(&world.resource::<DisplayImage>().0) else { return Ok(()) };
+
+        // The agent count lags behind by a few frames, agents spawned since are drawn once it catches up.
+        let count = world.resource::<SimulationReadback>().lock().unwrap().counters.count.min(MAX_AGENTS);
+        let instances = (count as f32 * settings.overlay_fraction).ceil() as u32;
+
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &display_image.texture_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_render_pipeline(draw_agents_pipeline);
+        pass.set_bind_group(0, &bind_group.0, &[]);
+        pass.draw(0..6, 0..instances);
+
+        Ok(())
+    }
+}
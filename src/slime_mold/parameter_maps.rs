@@ -0,0 +1,76 @@
+use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::TextureFormat}};
+
+
+pub const PARAMETER_MAPS: usize = 2;
+
+/// The simulation parameter a parameter map modulates.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MapTarget {
+    #[default]
+    None = 0,
+    MoveSpeed = 1,
+    TurnSpeed = 2,
+    SensorOffset = 3,
+    TrailWeight = 4,
+    DecayRate = 5,
+    DiffuseRate = 6,
+}
+
+impl MapTarget {
+    pub const ALL: [MapTarget; 7] = [
+        MapTarget::None,
+        MapTarget::MoveSpeed,
+        MapTarget::TurnSpeed,
+        MapTarget::SensorOffset,
+        MapTarget::TrailWeight,
+        MapTarget::DecayRate,
+        MapTarget::DiffuseRate,
+    ];
+}
+
+/// A grayscale image stretched over the trail map that scales one parameter per texel.
+/// With a strength of one the parameter is multiplied by the image value, with zero the map has no effect.
+#[derive(Clone)]
+pub struct ParameterMapSettings {
+    /// Image path, relative to the assets folder.
+    pub path: String,
+    pub target: MapTarget,
+    pub strength: f32,
+}
+
+impl Default for ParameterMapSettings {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            target: MapTarget::None,
+            strength: 1.0,
+        }
+    }
+}
+
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct ParameterMapImages(pub [Option<Handle<Image>>; PARAMETER_MAPS]);
+
+/// A flow field image whose red and green channels map from `[0, 1]` to a velocity in `[-1, 1]`.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct FlowMapImage(pub Option<Handle<Image>>);
+
+/// Map images hold data rather than colors, so they are sampled without the sRGB conversion images are loaded with.
+pub fn linearize_map_images(
+    mut events: EventReader<AssetEvent<Image>>,
+    parameter_maps: Res<ParameterMapImages>,
+    flow_map: Res<FlowMapImage>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for event in events.iter() {
+        let AssetEvent::Created { handle } = event else { continue };
+        let is_map = parameter_maps.0.iter().chain(std::iter::once(&flow_map.0)).flatten().any(|map| map == handle);
+        if !is_map { continue; }
+
+        if let Some(image) = images.get_mut(handle) {
+            if image.texture_descriptor.format == TextureFormat::Rgba8UnormSrgb {
+                image.texture_descriptor.format = TextureFormat::Rgba8Unorm;
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{prelude::*, render::{render_resource::{Buffer, BufferDescriptor, BufferUsages, MapMode, CommandEncoder, Texture, Extent3d, ImageCopyBuffer, ImageDataLayout}, renderer::RenderDevice}};
+
+use super::{texture::SimulationSize, buffers::{SlimeMoldAgentsBuffer, SlimeMoldTrailBuffers, AgentCounters}, checkpoint::SimulationSnapshot, statistics::StatisticsSample};
+
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadbackState {
+    #[default]
+    Idle,
+    Copied,
+    Mapping,
+    Mapped,
+}
+
+/// Bytes per row of an `R32Float` texture copied into a buffer, where rows must be aligned to 256 bytes.
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    (width * std::mem::size_of::<f32>() as u32 + 255) / 256 * 256
+}
+
+/// Removes the row alignment from an `R32Float` texture copied into a buffer.
+pub fn strip_row_padding(data: &[u8], width: u32) -> Vec<u8> {
+    let row = width as usize * std::mem::size_of::<f32>();
+    data.chunks(padded_bytes_per_row(width) as usize)
+        .flat_map(|padded_row| &padded_row[..row])
+        .copied()
+        .collect()
+}
+
+/// A staging buffer for copying GPU data back to the CPU without stalling the frame.
+/// The copy is recorded in the render graph, the buffer is mapped once the frame has been submitted,
+/// and the data becomes available a frame or two later.
+pub struct ReadbackBuffer {
+    pub buffer: Buffer,
+    pub size: u64,
+    state: Arc<Mutex<ReadbackState>>,
+}
+
+impl ReadbackBuffer {
+    pub fn new(device: &RenderDevice, size: u64) -> Self {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            size,
+            state: Arc::new(Mutex::new(ReadbackState::Idle)),
+        }
+    }
+
+    /// Whether a new copy can be recorded.
+    pub fn is_idle(&self) -> bool {
+        *self.state.lock().unwrap() == ReadbackState::Idle
+    }
+
+    /// Records a copy of the start of `source` into the staging buffer, unless a readback is already in flight.
+    pub fn copy_from(&self, encoder: &mut CommandEncoder, source: &Buffer) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != ReadbackState::Idle { return false; }
+
+        encoder.copy_buffer_to_buffer(source, 0, &self.buffer, 0, self.size);
+        *state = ReadbackState::Copied;
+        true
+    }
+
+    /// Records a copy of `source` into the staging buffer, unless a readback is already in flight.
+    /// Rows of `bytes_per_row` must be a multiple of 256 bytes.
+    pub fn copy_from_texture(&self, encoder: &mut CommandEncoder, source: &Texture, size: Extent3d, bytes_per_row: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != ReadbackState::Idle { return false; }
+
+        encoder.copy_texture_to_buffer(
+            source.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        *state = ReadbackState::Copied;
+        true
+    }
+
+    /// Must run after the frame has been submitted. Starts mapping a recorded copy, and returns the data once it is mapped.
+    pub fn poll(&self, device: &RenderDevice) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let current = *state;
+        match current {
+            ReadbackState::Copied => {
+                *state = ReadbackState::Mapping;
+                drop(state);
+
+                let callback_state = self.state.clone();
+                device.map_buffer(&self.buffer.slice(..), MapMode::Read, move |result| {
+                    *callback_state.lock().unwrap() = match result {
+                        Ok(()) => ReadbackState::Mapped,
+                        Err(_) => ReadbackState::Idle,
+                    };
+                });
+                None
+            }
+            ReadbackState::Mapped => {
+                let data = self.buffer.slice(..).get_mapped_range().to_vec();
+                self.buffer.unmap();
+                *state = ReadbackState::Idle;
+                Some(data)
+            }
+            ReadbackState::Idle | ReadbackState::Mapping => None,
+        }
+    }
+}
+
+
+/// Simulation data read back from the GPU, shared between the main world and the render world.
+#[derive(Resource, Clone, Default, Deref)]
+pub struct SimulationReadback(Arc<Mutex<ReadbackData>>);
+
+#[derive(Default, Clone)]
+pub struct ReadbackData {
+    pub counters: AgentCounters,
+    /// Set from the UI to read back the volume trail map, cleared once the copy is recorded.
+    pub volume_requested: bool,
+    /// Volume trail map waiting to be exported.
+    pub volume: Option<Vec<u8>>,
+    /// Set to read back every agent slot, cleared once the copy is recorded.
+    pub agents_requested: bool,
+    /// Tick of the last recorded agent copy.
+    pub agents_tick: u64,
+    /// Agent slots waiting to be exported, along with their tick.
+    pub agents: Option<(u64, Vec<u8>)>,
+    /// Set to read back the trail map and reaction-diffusion layer for export, cleared once the copies are recorded.
+    pub trail_requested: bool,
+    /// Set to read back the trail map for network extraction, cleared once the copies are recorded.
+    pub network_requested: bool,
+    /// The recorded trail copy, whose data is filled in once it is mapped.
+    pub trail_recorded: Option<TrailExport>,
+    /// Trail map waiting to be exported or analyzed.
+    pub trail: Option<TrailExport>,
+    /// Number of simulation steps run so far.
+    pub tick: u64,
+    /// Path to save a checkpoint to, cleared once its copies are recorded.
+    pub checkpoint_requested: Option<String>,
+    /// Path of the checkpoint whose copies are in flight, and its snapshot, whose data is filled in once it is mapped.
+    pub checkpoint_recorded: Option<(String, SimulationSnapshot)>,
+    /// Checkpoint state waiting to be saved.
+    pub checkpoint: Option<(String, SimulationSnapshot)>,
+    /// Checkpoint state waiting to be written back to the GPU.
+    pub restore: Option<SimulationSnapshot>,
+    /// Set to reduce the statistics, cleared once the copy is recorded.
+    pub stats_requested: bool,
+    /// Tick and number of texels of the statistics copy in flight.
+    pub stats_recorded: Option<(u64, u64)>,
+    /// Statistics sample waiting to be added to the history.
+    pub stats: Option<StatisticsSample>,
+}
+
+/// The trail map and the reaction-diffusion layer at one tick, in row major order without padding.
+#[derive(Clone)]
+pub struct TrailExport {
+    pub tick: u64,
+    pub size: SimulationSize,
+    pub trail: Vec<u8>,
+    /// The substrate `u` and activator `v` of each texel.
+    pub reaction: Vec<u8>,
+    /// Whether the trail map is waiting to be written to disk.
+    pub export: bool,
+    /// Whether the trail map is waiting to be analyzed for its network.
+    pub network: bool,
+}
+
+pub fn readback_agent_counters(
+    device: Res<RenderDevice>,
+    agents_buf: Res<SlimeMoldAgentsBuffer>,
+    readback: Res<SimulationReadback>,
+) {
+    if let Some(data) = agents_buf.counters_readback.poll(&device) {
+        readback.lock().unwrap().counters = bytemuck::pod_read_unaligned(&data);
+    }
+}
+
+pub fn readback_stats(
+    device: Res<RenderDevice>,
+    agents_buf: Res<SlimeMoldAgentsBuffer>,
+    readback: Res<SimulationReadback>,
+) {
+    if let Some(data) = agents_buf.stats_readback.poll(&device) {
+        let mut readback = readback.lock().unwrap();
+        if let Some((tick, texels)) = readback.stats_recorded.take() {
+            readback.stats = Some(StatisticsSample::from_sums(tick, texels, &bytemuck::pod_read_unaligned(&data)));
+        }
+    }
+}
+
+/// Staging buffer for every agent slot, only allocated while a copy of them is requested or in flight.
+#[derive(Resource, Default)]
+pub struct AgentsReadback(pub Option<ReadbackBuffer>);
+
+/// Allocates the agent staging buffer for a requested copy.
+pub fn prepare_agents_readback(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    agents_buf: Res<SlimeMoldAgentsBuffer>,
+    mut agents_readback: ResMut<AgentsReadback>,
+) {
+    if agents_readback.0.is_some() || !readback.lock().unwrap().agents_requested { return; }
+    agents_readback.0 = Some(ReadbackBuffer::new(&device, agents_buf.size));
+}
+
+/// Collects the agent copy and frees the staging buffer.
+pub fn readback_agents(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    mut agents_readback: ResMut<AgentsReadback>,
+) {
+    let Some(data) = agents_readback.0.as_ref().and_then(|buffer| buffer.poll(&device)) else { return };
+    agents_readback.0 = None;
+
+    let mut readback = readback.lock().unwrap();
+    readback.agents = Some((readback.agents_tick, data));
+}
+
+/// Staging buffers for the trail map and the reaction-diffusion layer, only allocated while a copy of them is requested or in flight.
+pub struct TrailReadbackBuffers {
+    pub size: SimulationSize,
+    pub trail: ReadbackBuffer,
+    pub reaction: ReadbackBuffer,
+    data: [Option<Vec<u8>>; 2],
+}
+
+#[derive(Resource, Default)]
+pub struct TrailReadback(pub Option<TrailReadbackBuffers>);
+
+/// Allocates the trail staging buffers for a requested copy, at the current size of the trail map.
+pub fn prepare_trail_readback(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    trail_buf: Res<SlimeMoldTrailBuffers>,
+    mut trail_readback: ResMut<TrailReadback>,
+) {
+    let readback = readback.lock().unwrap();
+    if !readback.trail_requested && !readback.network_requested { return; }
+    if trail_readback.0.as_ref().map_or(false, |buffers| buffers.size == trail_buf.size) { return; }
+
+    let size = trail_buf.size;
+    trail_readback.0 = Some(TrailReadbackBuffers {
+        size,
+        trail: ReadbackBuffer::new(&device, padded_bytes_per_row(size.width) as u64 * size.height as u64),
+        reaction: ReadbackBuffer::new(&device, size.texels() * std::mem::size_of::<[f32; 2]>() as u64),
+        data: Default::default(),
+    });
+}
+
+/// Collects the trail map and reaction-diffusion copies, which become available independently of each other,
+/// and frees the staging buffers once both are in.
+pub fn readback_trail(
+    device: Res<RenderDevice>,
+    readback: Res<SimulationReadback>,
+    mut trail_readback: ResMut<TrailReadback>,
+) {
+    let Some(buffers) = &mut trail_readback.0 else { return };
+    for (data, source) in buffers.data.iter_mut().zip([&buffers.trail, &buffers.reaction]) {
+        if data.is_none() {
+            *data = source.poll(&device);
+        }
+    }
+    if buffers.data.iter().any(Option::is_none) { return; }
+    let [Some(padded_trail), Some(reaction)] = std::mem::take(&mut buffers.data) else { return };
+    trail_readback.0 = None;
+
+    let mut readback = readback.lock().unwrap();
+    if let Some(recorded) = readback.trail_recorded.take() {
+        readback.trail = Some(TrailExport {
+            trail: strip_row_padding(&padded_trail, recorded.size.width),
+            reaction,
+            ..recorded
+        });
+    }
+}
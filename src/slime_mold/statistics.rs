@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use super::{buffers::{StatisticsSums, HEADING_BINS}, readback::SimulationReadback, ui::UISettings};
+
+
+/// Number of samples kept for the graphs of the statistics panel.
+pub const HISTORY_LENGTH: usize = 240;
+/// Fixed point scale of the sums, see `STATS_SCALE` in the shader.
+const STATS_SCALE: f64 = 256.0;
+
+/// The simulation statistics at one tick.
+#[derive(Clone, Copy, Default)]
+pub struct StatisticsSample {
+    pub tick: u64,
+    pub trail_mass: f32,
+    pub mean_intensity: f32,
+    pub max_intensity: f32,
+    /// Fraction of texels above the coverage threshold.
+    pub coverage: f32,
+    /// Mean distance per second the living agents actually covered in their last step, in texels.
+    pub mean_speed: f32,
+    /// Entropy of the agent headings, from zero when all agents head the same way to one when they are spread evenly.
+    pub heading_entropy: f32,
+}
+
+impl StatisticsSample {
+    pub fn from_sums(tick: u64, texels: u64, sums: &StatisticsSums) -> Self {
+        let sum = |[low, high]: [u32; 2]| ((high as u64) << 32 | low as u64) as f64 / STATS_SCALE;
+        let texels = texels.max(1) as f64;
+        let agents = sums.agents.max(1) as f64;
+        let trail_mass = sum(sums.trail_sum);
+
+        let heading_entropy = sums.headings.iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / agents;
+                -p * p.log2()
+            })
+            .sum::<f64>() / (HEADING_BINS as f64).log2();
+
+        Self {
+            tick,
+            trail_mass: trail_mass as f32,
+            mean_intensity: (trail_mass / texels) as f32,
+            max_intensity: f32::from_bits(sums.trail_max),
+            coverage: (sums.covered as f64 / texels) as f32,
+            mean_speed: (sum(sums.speed_sum) / agents) as f32,
+            heading_entropy: heading_entropy as f32,
+        }
+    }
+}
+
+/// The most recent statistics samples, oldest first.
+#[derive(Resource, Default)]
+pub struct StatisticsHistory {
+    pub samples: VecDeque<StatisticsSample>,
+}
+
+impl StatisticsHistory {
+    pub fn latest(&self) -> Option<&StatisticsSample> {
+        self.samples.back()
+    }
+
+    /// One statistic of every sample, for plotting.
+    pub fn series(&self, value: impl Fn(&StatisticsSample) -> f32) -> Vec<f32> {
+        self.samples.iter().map(value).collect()
+    }
+}
+
+/// Requests the statistics every `stats_interval` ticks and collects the samples read back.
+pub fn update_statistics(
+    settings: Res<UISettings>,
+    readback: Res<SimulationReadback>,
+    mut history: ResMut<StatisticsHistory>,
+    mut last_tick: Local<u64>,
+) {
+    let mut readback = readback.lock().unwrap();
+    let interval = settings.stats_interval as u64;
+    if interval > 0 && readback.tick / interval != *last_tick / interval {
+        readback.stats_requested = true;
+    }
+    *last_tick = readback.tick;
+
+    if let Some(sample) = readback.stats.take() {
+        // Restoring a checkpoint can move the clock back, which starts a new history.
+        if history.latest().map_or(false, |latest| sample.tick < latest.tick) {
+            history.samples.clear();
+        }
+        if history.samples.len() == HISTORY_LENGTH {
+            history.samples.pop_front();
+        }
+        history.samples.push_back(sample);
+    }
+}
@@ -1,11 +1,13 @@
 use bevy::{prelude::*, window::PrimaryWindow, render::{render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages}, extract_resource::ExtractResource}};
 
-use super::TEXTURE_SIZE;
+use super::{TEXTURE_SIZE, MAX_TEXELS, TEX_WORKGROUP_SIZE, VOLUME_DISPLAY_SIZE, ui::{UISettings, SimulationMode, FitMode}};
 
 pub fn setup_texture(
     mut commands: Commands, 
     window_query: Query<&Window, With<PrimaryWindow>>, 
-    mut images: ResMut<Assets<Image>>
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     let mut image = Image::new_fill(
         Extent3d {
@@ -15,27 +17,281 @@ pub fn setup_texture(
         },
         TextureDimension::D2,
         &[0, 0, 0, 0],
-        TextureFormat::Rgba8Unorm,
+        TextureFormat::R32Float,
     );
     image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+        TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
     let image = images.add(image);
 
+    let mut display_image = Image::new_fill(
+        Extent3d {
+            width: TEXTURE_SIZE.0,
+            height: TEXTURE_SIZE.1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 8],
+        TextureFormat::Rgba16Float,
+    );
+    display_image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT;
+    let display_image = images.add(display_image);
+
     let window = window_query.get_single().unwrap();
 
-    commands.spawn(SpriteBundle {
-        sprite: Sprite {
-            custom_size: Some(Vec2::new(window.width(), window.height())),
+    commands.spawn((
+        SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(window.width(), window.height())),
+                ..default()
+            },
+            texture: display_image.clone(),
+            ..default()
+        },
+        SlimeMoldSprite,
+    ));
+    commands.spawn(Camera2dBundle {
+        camera: Camera {
+            hdr: true,
+            ..default()
+        },
+        ..default()
+    });
+
+    // In sphere mode the trail map is equirectangular and wrapped around a sphere instead.
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(shape::UVSphere { radius: 1.0, sectors: 128, stacks: 64 }.into()),
+            material: materials.add(StandardMaterial {
+                base_color_texture: Some(display_image.clone()),
+                unlit: true,
+                ..default()
+            }),
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        SlimeMoldSphere,
+    ));
+    commands.spawn(Camera3dBundle {
+        camera: Camera {
+            is_active: false,
+            hdr: true,
             ..default()
         },
-        texture: image.clone(),
+        transform: Transform::from_xyz(0.0, 0.0, 3.0).looking_at(Vec3::ZERO, Vec3::Y),
         ..default()
     });
-    commands.spawn(Camera2dBundle::default());
 
     commands.insert_resource(SlimeMoldImage(image));
+    commands.insert_resource(DisplayImage(display_image));
+}
+
+/// Switches between the sprite and the rotating sphere depending on the simulation mode.
+pub fn update_surface_display(
+    settings: Res<UISettings>,
+    time: Res<Time>,
+    mut sprite_query: Query<&mut Visibility, With<SlimeMoldSprite>>,
+    mut sphere_query: Query<(&mut Visibility, &mut Transform), (With<SlimeMoldSphere>, Without<SlimeMoldSprite>)>,
+    mut camera_2d_query: Query<&mut Camera, With<Camera2d>>,
+    mut camera_3d_query: Query<&mut Camera, (With<Camera3d>, Without<Camera2d>)>,
+) {
+    let sphere = settings.mode == SimulationMode::Sphere;
+    let visibility = |visible: bool| if visible { Visibility::Inherited } else { Visibility::Hidden };
+
+    for mut sprite_visibility in &mut sprite_query {
+        sprite_visibility.set_if_neq(visibility(!sphere));
+    }
+    for (mut sphere_visibility, mut transform) in &mut sphere_query {
+        sphere_visibility.set_if_neq(visibility(sphere));
+        if sphere {
+            transform.rotate_y(settings.sphere_rotation_speed.to_radians() * time.delta_seconds());
+        }
+    }
+    for mut camera in &mut camera_2d_query {
+        if camera.is_active == sphere { camera.is_active = !sphere; }
+    }
+    for mut camera in &mut camera_3d_query {
+        if camera.is_active != sphere { camera.is_active = sphere; }
+    }
+}
+
+/// Seconds the window size has to stay the same before the simulation texture follows it.
+const RESIZE_DELAY: f32 = 0.25;
+
+/// The size the simulation texture should have for the settings, following the window when enabled.
+/// On the sphere the trail map is equirectangular, so it is twice as wide as it is high.
+pub fn target_simulation_size(settings: &UISettings, window: Option<&Window>) -> SimulationSize {
+    let size = match (settings.match_window_size, window) {
+        (true, Some(window)) => SimulationSize::fitting(
+            window.physical_width() as f32 * settings.resolution_scale,
+            window.physical_height() as f32 * settings.resolution_scale,
+        ),
+        _ => SimulationSize::default(),
+    };
+    if settings.mode == SimulationMode::Sphere { size.equirectangular() } else { size }
+}
+
+/// Sizes the simulation texture to the window when enabled in the settings, once the window stops being resized.
+/// Both images are resized in place, so their handles stay valid, and the render world follows the new size.
+pub fn update_simulation_size(
+    settings: Res<UISettings>,
+    time: Res<Time>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    slime_mold_image: Res<SlimeMoldImage>,
+    display_image: Res<DisplayImage>,
+    mut simulation_size: ResMut<SimulationSize>,
+    mut images: ResMut<Assets<Image>>,
+    mut pending: Local<Option<(SimulationSize, f32)>>,
+) {
+    let target = target_simulation_size(&settings, window_query.get_single().ok());
+
+    if target == *simulation_size {
+        *pending = None;
+        return;
+    }
+    match *pending {
+        Some((size, since)) if size == target => {
+            if time.elapsed_seconds() - since < RESIZE_DELAY { return; }
+        }
+        _ => {
+            *pending = Some((target, time.elapsed_seconds()));
+            return;
+        }
+    }
+
+    let extent = Extent3d {
+        width: target.width,
+        height: target.height,
+        depth_or_array_layers: 1,
+    };
+    for handle in [&slime_mold_image.0, &display_image.0] {
+        if let Some(image) = images.get_mut(handle) {
+            image.resize(extent);
+        }
+    }
+    *simulation_size = target;
+    *pending = None;
+}
+
+/// Sizes the sprite to the window according to the fit mode, keeping up with window resizes.
+pub fn update_sprite_size(
+    settings: Res<UISettings>,
+    simulation_size: Res<SimulationSize>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut sprite_query: Query<&mut Sprite, With<SlimeMoldSprite>>,
+) {
+    let Ok(window) = window_query.get_single() else { return };
+    let window_size = Vec2::new(window.width(), window.height());
+    let texture_size = match settings.mode {
+        SimulationMode::Flat | SimulationMode::Sphere => simulation_size.as_vec2(),
+        SimulationMode::Volume => Vec2::new(VOLUME_DISPLAY_SIZE.0 as f32, VOLUME_DISPLAY_SIZE.1 as f32),
+    };
+
+    let fit_scale = (window_size / texture_size).min_element();
+    let fill_scale = (window_size / texture_size).max_element();
+    let size = match settings.fit_mode {
+        FitMode::Fit => texture_size * fit_scale,
+        FitMode::Fill => texture_size * fill_scale,
+        FitMode::Stretch => window_size,
+        FitMode::Actual => texture_size / window.scale_factor() as f32,
+    };
+
+    for mut sprite in &mut sprite_query {
+        if sprite.custom_size != Some(size) {
+            sprite.custom_size = Some(size);
+        }
+    }
+}
+
+/// Maps a cursor position in window coordinates to a texel position in the simulation texture,
+/// going through the camera and the sprite the texture is displayed on.
+pub fn cursor_to_texture(
+    cursor: Vec2,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    sprite: &Sprite,
+    sprite_transform: &GlobalTransform,
+    texture_size: Vec2,
+) -> Option<Vec2> {
+    let world = camera.viewport_to_world_2d(camera_transform, cursor)?;
+    let size = sprite.custom_size?;
+    let local = sprite_transform.affine().inverse().transform_point3(world.extend(0.0));
+
+    let uv = Vec2::new(local.x / size.x + 0.5, 0.5 - local.y / size.y);
+    if uv.cmplt(Vec2::ZERO).any() || uv.cmpge(Vec2::ONE).any() {
+        return None;
+    }
+
+    Some(uv * texture_size)
+}
+
+/// Maps a position in texels to world space over the sprite, the inverse of `cursor_to_texture`.
+pub fn texture_to_world(position: Vec2, sprite_size: Vec2, sprite_transform: &GlobalTransform, texture_size: Vec2) -> Vec2 {
+    let uv = position / texture_size;
+    let local = Vec3::new((uv.x - 0.5) * sprite_size.x, (0.5 - uv.y) * sprite_size.y, 0.0);
+    sprite_transform.transform_point(local).truncate()
+}
+
+
+/// Size of the trail map the simulation runs on, in texels.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, ExtractResource)]
+pub struct SimulationSize {
+    pub width: u32,
+    pub height: u32,
 }
 
+impl Default for SimulationSize {
+    fn default() -> Self {
+        Self {
+            width: TEXTURE_SIZE.0,
+            height: TEXTURE_SIZE.1,
+        }
+    }
+}
+
+impl SimulationSize {
+    /// The largest size within `width` by `height` that is a whole number of workgroups and stays under `MAX_TEXELS`.
+    pub fn fitting(width: f32, height: f32) -> Self {
+        let scale = (MAX_TEXELS as f32 / (width * height)).sqrt().min(1.0);
+        let round = |length: f32| ((length * scale) as u32 / TEX_WORKGROUP_SIZE).max(8) * TEX_WORKGROUP_SIZE;
+        Self {
+            width: round(width),
+            height: round(height),
+        }
+    }
+
+    /// A size twice as wide as it is high with about as many texels, so every texel of an equirectangular map spans the same angle both ways.
+    pub fn equirectangular(&self) -> Self {
+        let height = ((self.texels() as f32 / 2.0).sqrt() as u32 / TEX_WORKGROUP_SIZE).max(8) * TEX_WORKGROUP_SIZE;
+        Self {
+            width: 2 * height,
+            height,
+        }
+    }
+
+    pub fn texels(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
 
+    pub fn workgroups(&self) -> (u32, u32) {
+        (self.width / TEX_WORKGROUP_SIZE, self.height / TEX_WORKGROUP_SIZE)
+    }
+
+    pub fn as_vec2(&self) -> Vec2 {
+        Vec2::new(self.width as f32, self.height as f32)
+    }
+}
+
+/// The trail map the simulation runs on.
 #[derive(Resource, Clone, Deref, ExtractResource)]
 pub struct SlimeMoldImage(pub Handle<Image>);
+
+/// The colored trail map, in high dynamic range.
+#[derive(Resource, Clone, Deref, ExtractResource)]
+pub struct DisplayImage(pub Handle<Image>);
+
+#[derive(Component)]
+pub struct SlimeMoldSprite;
+
+#[derive(Component)]
+pub struct SlimeMoldSphere;
@@ -1,6 +1,8 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, core_pipeline::tonemapping::Tonemapping};
 use bevy_egui::{egui, EguiContexts};
 
+use super::{brush::BrushTool, readback::SimulationReadback, parameter_maps::{ParameterMapSettings, ParameterMapImages, MapTarget, PARAMETER_MAPS, FlowMapImage}, gradient::{ColorGradient, ColorStop, GradientPresets, Palette}, display::TONEMAPPING_OPERATORS, overlay::OverlayStyle, checkpoint::{CheckpointLoad, CHECKPOINT_EXTENSION}, export::{export_path, AgentExportFormat, TrailExportFormat}, network::{NetworkAnalysis, NetworkExportFormat, export_network}, statistics::{StatisticsHistory, StatisticsSample}};
+
 
 #[derive(Resource, Default, PartialEq, Clone)]
 pub enum UIVisibility {
@@ -9,21 +11,248 @@ pub enum UIVisibility {
     Hidden,
 }
 
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DiffusionKernel {
+    #[default]
+    Box = 0,
+    Gaussian = 1,
+    /// A gaussian kernel with a different falloff along the vertical axis.
+    Anisotropic = 2,
+}
+
+impl DiffusionKernel {
+    pub const ALL: [DiffusionKernel; 3] = [DiffusionKernel::Box, DiffusionKernel::Gaussian, DiffusionKernel::Anisotropic];
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SensorModel {
+    /// Sums the square of texels around each sensor.
+    #[default]
+    Block = 0,
+    /// Sums the disk of texels around each sensor.
+    Circular = 1,
+    /// Samples a single bilinearly interpolated point at each sensor.
+    Bilinear = 2,
+}
+
+impl SensorModel {
+    pub const ALL: [SensorModel; 3] = [SensorModel::Block, SensorModel::Circular, SensorModel::Bilinear];
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SteeringModel {
+    /// Compares the forward sensor with the strongest sensor on either side.
+    #[default]
+    Classic = 0,
+    /// Turns towards the softmax weighted average of all sensor angles.
+    Weighted = 1,
+    /// Turns proportionally to the difference between both sides.
+    Gradient = 2,
+}
+
+impl SteeringModel {
+    pub const ALL: [SteeringModel; 3] = [SteeringModel::Classic, SteeringModel::Weighted, SteeringModel::Gradient];
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SimulationMode {
+    /// Agents move over the flat trail map.
+    #[default]
+    Flat,
+    /// Agents move through a volume trail map.
+    Volume,
+    /// Agents move along great circles over an equirectangular trail map wrapped around a sphere.
+    Sphere,
+}
+
+impl SimulationMode {
+    pub const ALL: [SimulationMode; 3] = [SimulationMode::Flat, SimulationMode::Volume, SimulationMode::Sphere];
+}
+
+/// How the simulation texture is sized to the window.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FitMode {
+    /// Shows the whole texture, with bars on the sides that do not match its aspect ratio.
+    #[default]
+    Fit,
+    /// Covers the whole window, cropping the sides that do not match its aspect ratio.
+    Fill,
+    /// Covers the whole window, ignoring the aspect ratio.
+    Stretch,
+    /// One texel per physical pixel.
+    Actual,
+}
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VolumeView {
+    /// Raymarches the volume from a camera orbiting around it.
+    #[default]
+    Projection = 0,
+    /// Shows a single slice through the volume.
+    Slice = 1,
+}
+
+/// What the display shows, the simulation itself is unaffected.
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DisplayMode {
+    /// Trail intensity through the color gradient.
+    #[default]
+    Trail = 0,
+    /// Trail intensity in grayscale, without the gradient or clamping.
+    RawTrail = 1,
+    /// Number of agents in each texel.
+    AgentDensity = 2,
+    /// Mean heading of the agents in each texel as hue.
+    Heading = 3,
+    /// How much the sensors of the agents in each texel disagree.
+    SensorDisagreement = 4,
+}
+
+impl DisplayMode {
+    /// Whether the mode needs agents splatted into the trail map before display.
+    pub fn splats_agents(&self) -> bool {
+        matches!(self, DisplayMode::Heading | DisplayMode::SensorDisagreement)
+    }
+}
+
 #[derive(Resource, Clone)]
 pub struct UISettings {
+    pub mode: SimulationMode,
+
     pub move_speed: f32,
     pub turn_speed: f32,
+    pub steering_model: SteeringModel,
+    pub steering_temperature: f32,
+    /// Moves agents along the heading chosen this step rather than the previous one.
+    pub apply_new_angle: bool,
 
     pub trail_weight: f32,
     pub decay_rate: f32,
     pub diffuse_rate: f32,
+    pub diffusion_kernel: DiffusionKernel,
+    pub diffusion_radius: i32,
+    pub diffusion_sigma: f32,
+    pub diffusion_anisotropy: f32,
 
     pub sensor_angle_spacing: f32,
     pub sensor_offset_dst: f32,
     pub sensor_size: i32,
+    pub sensor_model: SensorModel,
+    /// Number of sensors fanned across the sensor angle, always odd.
+    pub sensor_count: u32,
+    pub sensor_noise: f32,
+
+    /// Only lets an agent move into an unoccupied texel.
+    pub occupancy_limited: bool,
+
+    pub gradient: ColorGradient,
+    pub gradient_preset_name: String,
+
+    pub brush_tool: BrushTool,
+    pub brush_radius: f32,
+    pub brush_strength: f32,
+    pub spawn_rate: f32,
+
+    pub lifecycle: bool,
+    /// Maximum age of an agent in seconds, or zero for no limit.
+    pub lifetime: f32,
+    pub crowd_limit: u32,
+    pub death_threshold: f32,
+    pub birth_threshold: f32,
+    pub birth_rate: f32,
+
+    pub parameter_maps: [ParameterMapSettings; PARAMETER_MAPS],
+
+    /// Direction the wind blows towards in degrees, counterclockwise from the right.
+    pub wind_direction: f32,
+    /// Wind speed in texels per second.
+    pub wind_strength: f32,
+    /// Flow map image path, relative to the assets folder.
+    pub flow_map_path: String,
+    /// Flow speed in texels per second at a full flow map value.
+    pub flow_map_strength: f32,
+
+    pub volume_move_speed: f32,
+    pub volume_sensor_offset: f32,
+    pub volume_view: VolumeView,
+    /// Depth of the displayed slice, from zero to one.
+    pub volume_slice: f32,
+    /// Orbit speed of the projection camera in degrees per second.
+    pub volume_rotation_speed: f32,
+    pub volume_density: f32,
 
-    pub color_a: [f32; 3],
-    pub color_b: [f32; 3],
+    /// Spin of the displayed sphere in degrees per second.
+    pub sphere_rotation_speed: f32,
+
+    /// Runs a Gray-Scott reaction-diffusion layer next to the trail map.
+    /// Agents deposit the activator `v` and sense the substrate `u`.
+    pub reaction_diffusion: bool,
+    pub feed_rate: f32,
+    pub kill_rate: f32,
+    pub diffusion_rate_u: f32,
+    pub diffusion_rate_v: f32,
+    /// Time step of each of the two reaction-diffusion steps per frame.
+    pub reaction_speed: f32,
+    pub reaction_deposit: f32,
+    /// Weight of the substrate in what agents sense, negative values repel.
+    pub reaction_sensing: f32,
+    /// Colors the trail map by the activator instead of the trail.
+    pub show_reaction: bool,
+
+    pub fit_mode: FitMode,
+    /// Sizes the trail map to the window instead of the default size.
+    pub match_window_size: bool,
+    /// Scale of the trail map relative to the physical size of the window.
+    pub resolution_scale: f32,
+    pub display_mode: DisplayMode,
+    /// Agents per texel shown at the end of the gradient in the agent density view.
+    pub density_scale: f32,
+
+    /// Draws agents on top of the display.
+    pub agent_overlay: bool,
+    pub overlay_style: OverlayStyle,
+    /// Size of the drawn agents in texels.
+    pub overlay_size: f32,
+    /// Fraction of the agents that are drawn.
+    pub overlay_fraction: f32,
+    pub overlay_color: Color,
+
+    /// Scale of the colors written to the display map, above one trails become brighter than white.
+    pub hdr_intensity: f32,
+    /// Exposure of the display in stops.
+    pub exposure: f32,
+    pub gamma: f32,
+    pub tonemapping: Tonemapping,
+    /// Strength of the bloom, zero disables it.
+    pub bloom_intensity: f32,
+    /// Brightness above which the display blooms.
+    pub bloom_threshold: f32,
+
+    /// Steps the simulation by `time_step` instead of the frame time, so runs restored from a checkpoint are repeatable.
+    pub deterministic: bool,
+    pub time_step: f32,
+    /// Offsets every random number the simulation draws, zero keeps the original sequence.
+    pub seed: u32,
+    /// Saves a checkpoint every this many ticks, or never when zero.
+    pub autosave_interval: u32,
+    pub checkpoint_path: String,
+
+    pub agent_export_format: AgentExportFormat,
+    /// Exports the agents every this many ticks, or only on demand when zero.
+    pub agent_export_interval: u32,
+    pub trail_export_format: TrailExportFormat,
+
+    /// Trail intensity above which a texel is part of the network.
+    pub network_threshold: f32,
+    /// Dangling branches shorter than this many texels are pruned from the network.
+    pub network_min_branch: f32,
+    pub network_overlay: bool,
+    pub network_color: Color,
+
+    /// Reduces the statistics every this many ticks, or never when zero.
+    pub stats_interval: u32,
+    /// Trail intensity above which a texel counts as covered.
+    pub stats_threshold: f32,
 
     pub running: bool,
 }
@@ -31,31 +260,151 @@ pub struct UISettings {
 impl Default for UISettings {
     fn default() -> Self {
         Self {
+            mode: SimulationMode::Flat,
+
             move_speed: 100.0,
             turn_speed: 10.0,
+            steering_model: SteeringModel::Classic,
+            steering_temperature: 0.1,
+            apply_new_angle: false,
 
             trail_weight: 50.0,
             decay_rate: 0.25,
             diffuse_rate: 5.0,
+            diffusion_kernel: DiffusionKernel::Box,
+            diffusion_radius: 1,
+            diffusion_sigma: 1.0,
+            diffusion_anisotropy: 2.0,
 
             sensor_angle_spacing: 15.0,
             sensor_offset_dst: 15.0,
             sensor_size: 3,
+            sensor_model: SensorModel::Block,
+            sensor_count: 3,
+            sensor_noise: 0.0,
+
+            occupancy_limited: false,
+
+            gradient: ColorGradient::default(),
+            gradient_preset_name: String::new(),
+
+            brush_tool: BrushTool::Trail,
+            brush_radius: 20.0,
+            brush_strength: 5.0,
+            spawn_rate: 50_000.0,
+
+            lifecycle: false,
+            lifetime: 0.0,
+            crowd_limit: 8,
+            death_threshold: 1.0,
+            birth_threshold: 0.5,
+            birth_rate: 0.1,
 
-            color_a: [1.0, 1.0, 1.0],
-            color_b: [0.0, 0.0, 0.0],
+            parameter_maps: Default::default(),
+
+            wind_direction: 0.0,
+            wind_strength: 0.0,
+            flow_map_path: String::new(),
+            flow_map_strength: 50.0,
+
+            volume_move_speed: 20.0,
+            volume_sensor_offset: 6.0,
+            volume_view: VolumeView::Projection,
+            volume_slice: 0.5,
+            volume_rotation_speed: 15.0,
+            volume_density: 0.05,
+
+            sphere_rotation_speed: 10.0,
+
+            reaction_diffusion: false,
+            feed_rate: 0.055,
+            kill_rate: 0.062,
+            diffusion_rate_u: 1.0,
+            diffusion_rate_v: 0.5,
+            reaction_speed: 1.0,
+            reaction_deposit: 1.0,
+            reaction_sensing: 1.0,
+            show_reaction: false,
+
+            fit_mode: FitMode::Fit,
+            match_window_size: false,
+            resolution_scale: 1.0,
+            display_mode: DisplayMode::Trail,
+            density_scale: 4.0,
+
+            agent_overlay: false,
+            overlay_style: OverlayStyle::Points,
+            overlay_size: 2.0,
+            overlay_fraction: 0.05,
+            overlay_color: Color::rgb(1.0, 0.3, 0.1),
+
+            hdr_intensity: 1.0,
+            exposure: 0.0,
+            gamma: 1.0,
+            tonemapping: Tonemapping::None,
+            bloom_intensity: 0.0,
+            bloom_threshold: 0.8,
+
+            deterministic: false,
+            time_step: 1.0 / 60.0,
+            seed: 0,
+            autosave_interval: 0,
+            checkpoint_path: String::new(),
+
+            agent_export_format: AgentExportFormat::Csv,
+            agent_export_interval: 0,
+            trail_export_format: TrailExportFormat::Npy,
+
+            network_threshold: 0.5,
+            network_min_branch: 10.0,
+            network_overlay: true,
+            network_color: Color::rgb(0.2, 0.9, 1.0),
+
+            stats_interval: 10,
+            stats_threshold: 0.1,
 
             running: false,
         }
     }
 }
 
+impl UISettings {
+    /// The uniform wind velocity in texture coordinates, where the y axis points down.
+    pub fn wind(&self) -> Vec2 {
+        let angle = self.wind_direction.to_radians();
+        Vec2::new(angle.cos(), -angle.sin()) * self.wind_strength
+    }
+}
+
+
+/// Draws `values` as a small line graph, scaled to fit their range.
+fn sparkline(ui: &mut egui::Ui, values: &[f32]) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width(), 24.0), egui::Sense::hover());
+    ui.painter().rect_stroke(rect, 0.0, ui.visuals().widgets.noninteractive.bg_stroke);
+    if values.len() < 2 { return; }
+
+    let (min, max) = values.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &value| (min.min(value), max.max(value)));
+    let range = (max - min).max(f32::EPSILON);
+    let points = values.iter().enumerate().map(|(i, &value)| egui::pos2(
+        rect.left() + rect.width() * i as f32 / (values.len() - 1) as f32,
+        rect.bottom() - rect.height() * (value - min) / range,
+    )).collect::<Vec<_>>();
+    ui.painter().add(egui::Shape::line(points, ui.visuals().widgets.noninteractive.fg_stroke));
+}
 
 pub fn ui_update(
     mut contexts: EguiContexts,
     mut ui_visibility: ResMut<UIVisibility>,
     keyboard: Res<Input<KeyCode>>,
     time: Res<Time>,
+    readback: Res<SimulationReadback>,
+    asset_server: Res<AssetServer>,
+    mut parameter_map_images: ResMut<ParameterMapImages>,
+    mut flow_map_image: ResMut<FlowMapImage>,
+    mut gradient_presets: ResMut<GradientPresets>,
+    mut checkpoint_load: ResMut<CheckpointLoad>,
+    mut network: ResMut<NetworkAnalysis>,
+    history: Res<StatisticsHistory>,
     mut settings: ResMut<UISettings>,
 ) {
     if keyboard.just_pressed(KeyCode::Tab) {
@@ -68,31 +417,379 @@ pub fn ui_update(
 
     egui::Window::new("Settings").show(contexts.ctx_mut(), |ui| {
         ui.label(format!("FPS: {:.1}", 1.0 / time.delta_seconds()));
+        ui.label(format!("Population: {}", readback.lock().unwrap().counters.alive));
         ui.label("Press [TAB] to Toggle UI");
 
         ui.separator();
 
+        egui::ComboBox::from_label("Mode")
+            .selected_text(format!("{:?}", settings.mode))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.mode, SimulationMode::Flat, "Flat");
+                ui.selectable_value(&mut settings.mode, SimulationMode::Volume, "Volume");
+                ui.selectable_value(&mut settings.mode, SimulationMode::Sphere, "Sphere");
+            });
+        if settings.mode == SimulationMode::Sphere {
+            ui.add(egui::widgets::DragValue::new(&mut settings.sphere_rotation_speed).prefix("Rotation Speed: ").suffix("°/s").speed(0.1).clamp_range(-180..=180));
+        }
+        if settings.mode == SimulationMode::Volume {
+            ui.add(egui::widgets::DragValue::new(&mut settings.volume_move_speed).prefix("Volume Move Speed: ").speed(0.1).clamp_range(0..=200));
+            ui.add(egui::widgets::DragValue::new(&mut settings.volume_sensor_offset).prefix("Volume Sensor Offset: ").speed(0.05).clamp_range(1..=32));
+            egui::ComboBox::from_label("View")
+                .selected_text(format!("{:?}", settings.volume_view))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.volume_view, VolumeView::Projection, "Projection");
+                    ui.selectable_value(&mut settings.volume_view, VolumeView::Slice, "Slice");
+                });
+            match settings.volume_view {
+                VolumeView::Projection => {
+                    ui.add(egui::widgets::DragValue::new(&mut settings.volume_rotation_speed).prefix("Rotation Speed: ").suffix("°/s").speed(0.1).clamp_range(-180..=180));
+                    ui.add(egui::widgets::DragValue::new(&mut settings.volume_density).prefix("Density: ").speed(0.001).clamp_range(0.001..=1));
+                }
+                VolumeView::Slice => {
+                    ui.add(egui::widgets::Slider::new(&mut settings.volume_slice, 0.0..=1.0).text("Slice"));
+                }
+            }
+            if ui.button("Export Volume").clicked() {
+                readback.lock().unwrap().volume_requested = true;
+            }
+        }
+
+        ui.separator();
+
         ui.add(egui::widgets::DragValue::new(&mut settings.move_speed).prefix("Move Speed: ").speed(0.1));
         ui.add(egui::widgets::DragValue::new(&mut settings.turn_speed).prefix("Turn Speed: ").speed(0.02));
+        egui::ComboBox::from_label("Steering")
+            .selected_text(format!("{:?}", settings.steering_model))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.steering_model, SteeringModel::Classic, "Classic");
+                ui.selectable_value(&mut settings.steering_model, SteeringModel::Weighted, "Weighted");
+                ui.selectable_value(&mut settings.steering_model, SteeringModel::Gradient, "Gradient");
+            });
+        if settings.steering_model == SteeringModel::Weighted {
+            ui.add(egui::widgets::DragValue::new(&mut settings.steering_temperature).prefix("Temperature: ").speed(0.005).clamp_range(0.01..=10.0));
+        }
+        ui.checkbox(&mut settings.apply_new_angle, "Move Along New Heading");
 
         ui.separator();
 
         ui.add(egui::widgets::DragValue::new(&mut settings.trail_weight).prefix("Trail Weight: ").speed(0.1));
         ui.add(egui::widgets::DragValue::new(&mut settings.decay_rate).prefix("Decay Rate: ").speed(0.01).clamp_range(0..=1));
         ui.add(egui::widgets::DragValue::new(&mut settings.diffuse_rate).prefix("Diffuse Rate: ").speed(0.02));
+        egui::ComboBox::from_label("Diffusion Kernel")
+            .selected_text(format!("{:?}", settings.diffusion_kernel))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.diffusion_kernel, DiffusionKernel::Box, "Box");
+                ui.selectable_value(&mut settings.diffusion_kernel, DiffusionKernel::Gaussian, "Gaussian");
+                ui.selectable_value(&mut settings.diffusion_kernel, DiffusionKernel::Anisotropic, "Anisotropic");
+            });
+        ui.add(egui::widgets::DragValue::new(&mut settings.diffusion_radius).prefix("Diffusion Radius: ").speed(0.05).clamp_range(1..=16));
+        if settings.diffusion_kernel != DiffusionKernel::Box {
+            ui.add(egui::widgets::DragValue::new(&mut settings.diffusion_sigma).prefix("Diffusion Sigma: ").speed(0.01).clamp_range(0.1..=16.0));
+        }
+        if settings.diffusion_kernel == DiffusionKernel::Anisotropic {
+            ui.add(egui::widgets::DragValue::new(&mut settings.diffusion_anisotropy).prefix("Anisotropy: ").speed(0.01).clamp_range(0.1..=10.0));
+        }
 
         ui.separator();
 
         ui.add(egui::widgets::DragValue::new(&mut settings.sensor_angle_spacing).prefix("Sensor Angle Spacing: ").suffix("°").speed(0.1));
         ui.add(egui::widgets::DragValue::new(&mut settings.sensor_offset_dst).prefix("Sensor Offset: ").speed(0.05));
-        ui.add(egui::widgets::DragValue::new(&mut settings.sensor_size).prefix("Sensor Size: ").speed(0.05).clamp_range(3..=7));
+        egui::ComboBox::from_label("Sensor Model")
+            .selected_text(format!("{:?}", settings.sensor_model))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut settings.sensor_model, SensorModel::Block, "Block");
+                ui.selectable_value(&mut settings.sensor_model, SensorModel::Circular, "Circular");
+                ui.selectable_value(&mut settings.sensor_model, SensorModel::Bilinear, "Bilinear");
+            });
+        if settings.sensor_model != SensorModel::Bilinear {
+            ui.add(egui::widgets::DragValue::new(&mut settings.sensor_size).prefix("Sensor Size: ").speed(0.05).clamp_range(3..=7));
+        }
+        if ui.add(egui::widgets::DragValue::new(&mut settings.sensor_count).prefix("Sensor Count: ").speed(0.05).clamp_range(3..=9)).changed() {
+            settings.sensor_count |= 1;
+        }
+        ui.add(egui::widgets::DragValue::new(&mut settings.sensor_noise).prefix("Sensor Noise: ").speed(0.005).clamp_range(0..=1));
 
         ui.separator();
 
-        ui.label("Primary Color");
-        egui::widgets::color_picker::color_edit_button_rgb(ui, &mut settings.color_a);
-        ui.label("Secondary Color");
-        egui::widgets::color_picker::color_edit_button_rgb(ui, &mut settings.color_b);
+        ui.checkbox(&mut settings.occupancy_limited, "One Agent per Texel");
+
+        ui.separator();
+
+        ui.checkbox(&mut settings.lifecycle, "Agent Lifecycle");
+        ui.add_enabled_ui(settings.lifecycle, |ui| {
+            ui.add(egui::widgets::DragValue::new(&mut settings.lifetime).prefix("Lifetime: ").suffix("s").speed(0.1).clamp_range(0..=3600));
+            ui.add(egui::widgets::DragValue::new(&mut settings.crowd_limit).prefix("Crowd Limit: ").speed(0.05).clamp_range(1..=64));
+            ui.add(egui::widgets::DragValue::new(&mut settings.death_threshold).prefix("Death Threshold: ").suffix("s").speed(0.01).clamp_range(0..=60));
+            ui.add(egui::widgets::DragValue::new(&mut settings.birth_threshold).prefix("Birth Threshold: ").speed(0.01).clamp_range(0..=1));
+            ui.add(egui::widgets::DragValue::new(&mut settings.birth_rate).prefix("Birth Rate: ").suffix("/s").speed(0.005).clamp_range(0..=10));
+        });
+
+        ui.separator();
+
+        ui.collapsing("Parameter Maps", |ui| {
+            for (i, map) in settings.parameter_maps.iter_mut().enumerate() {
+                ui.push_id(i, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut map.path);
+                        if ui.button("Load").clicked() && !map.path.is_empty() {
+                            parameter_map_images.0[i] = Some(asset_server.load(map.path.clone()));
+                        }
+                        if ui.button("Clear").clicked() {
+                            parameter_map_images.0[i] = None;
+                        }
+                    });
+                    egui::ComboBox::from_label("Target")
+                        .selected_text(format!("{:?}", map.target))
+                        .show_ui(ui, |ui| {
+                            for target in MapTarget::ALL {
+                                ui.selectable_value(&mut map.target, target, format!("{:?}", target));
+                            }
+                        });
+                    ui.add(egui::widgets::DragValue::new(&mut map.strength).prefix("Strength: ").speed(0.01).clamp_range(-2.0..=2.0));
+                });
+                ui.separator();
+            }
+        });
+
+        ui.collapsing("Wind", |ui| {
+            ui.add(egui::widgets::DragValue::new(&mut settings.wind_direction).prefix("Direction: ").suffix("°").speed(0.5).clamp_range(0..=360));
+            ui.add(egui::widgets::DragValue::new(&mut settings.wind_strength).prefix("Strength: ").speed(0.1).clamp_range(0..=500));
+            ui.label("Flow Map");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut settings.flow_map_path);
+                if ui.button("Load").clicked() && !settings.flow_map_path.is_empty() {
+                    flow_map_image.0 = Some(asset_server.load(settings.flow_map_path.clone()));
+                }
+                if ui.button("Clear").clicked() {
+                    flow_map_image.0 = None;
+                }
+            });
+            ui.add(egui::widgets::DragValue::new(&mut settings.flow_map_strength).prefix("Flow Strength: ").speed(0.1).clamp_range(0..=500));
+        });
+
+        ui.collapsing("Reaction-Diffusion", |ui| {
+            ui.checkbox(&mut settings.reaction_diffusion, "Gray-Scott Layer");
+            ui.add_enabled_ui(settings.reaction_diffusion, |ui| {
+                ui.add(egui::widgets::DragValue::new(&mut settings.feed_rate).prefix("Feed Rate: ").speed(0.0005).clamp_range(0..=0.1));
+                ui.add(egui::widgets::DragValue::new(&mut settings.kill_rate).prefix("Kill Rate: ").speed(0.0005).clamp_range(0..=0.1));
+                ui.add(egui::widgets::DragValue::new(&mut settings.diffusion_rate_u).prefix("Diffusion U: ").speed(0.005).clamp_range(0..=1));
+                ui.add(egui::widgets::DragValue::new(&mut settings.diffusion_rate_v).prefix("Diffusion V: ").speed(0.005).clamp_range(0..=1));
+                ui.add(egui::widgets::DragValue::new(&mut settings.reaction_speed).prefix("Speed: ").speed(0.005).clamp_range(0..=1));
+                ui.add(egui::widgets::DragValue::new(&mut settings.reaction_deposit).prefix("Agent Deposit: ").speed(0.005).clamp_range(0..=10));
+                ui.add(egui::widgets::DragValue::new(&mut settings.reaction_sensing).prefix("Substrate Sensing: ").speed(0.01).clamp_range(-10..=10));
+                ui.checkbox(&mut settings.show_reaction, "Show Activator");
+            });
+        });
+
+        ui.separator();
+
+        ui.collapsing("Color Gradient", |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for palette in Palette::ALL {
+                    if ui.button(format!("{:?}", palette)).clicked() {
+                        settings.gradient = palette.gradient();
+                    }
+                }
+            });
+
+            let stop_count = settings.gradient.stops.len();
+            let mut removed = None;
+            for (i, stop) in settings.gradient.stops.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    let [r, g, b, _] = stop.color.as_linear_rgba_f32();
+                    let mut rgb = [r, g, b];
+                    if egui::widgets::color_picker::color_edit_button_rgb(ui, &mut rgb).changed() {
+                        stop.color = Color::rgb_linear(rgb[0], rgb[1], rgb[2]);
+                    }
+                    ui.add(egui::widgets::DragValue::new(&mut stop.position).prefix("Position: ").speed(0.005).clamp_range(0..=1));
+                    if ui.add_enabled(stop_count > 2, egui::Button::new("Remove")).clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                settings.gradient.stops.remove(i);
+            }
+            if ui.button("Add Stop").clicked() {
+                let mut sorted = settings.gradient.clone();
+                sorted.sort();
+                let color = sorted.evaluate(0.5);
+                settings.gradient.stops.push(ColorStop { position: 0.5, color: Color::rgb_linear(color.x, color.y, color.z) });
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut settings.gradient_preset_name);
+                if ui.button("Save Preset").clicked() && !settings.gradient_preset_name.is_empty() {
+                    gradient_presets.insert(settings.gradient_preset_name.clone(), settings.gradient.clone());
+                    if let Err(err) = gradient_presets.save() {
+                        error!("Failed to save gradient presets: {}", err);
+                    }
+                }
+            });
+            egui::ComboBox::from_label("Presets")
+                .selected_text("Load Preset")
+                .show_ui(ui, |ui| {
+                    for (name, gradient) in &gradient_presets.0 {
+                        if ui.selectable_label(false, name).clicked() {
+                            settings.gradient = gradient.clone();
+                            settings.gradient_preset_name = name.clone();
+                        }
+                    }
+                });
+        });
+
+        ui.collapsing("Display", |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut settings.fit_mode, FitMode::Fit, "Fit");
+                ui.selectable_value(&mut settings.fit_mode, FitMode::Fill, "Fill");
+                ui.selectable_value(&mut settings.fit_mode, FitMode::Stretch, "Stretch");
+                ui.selectable_value(&mut settings.fit_mode, FitMode::Actual, "1:1");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.match_window_size, "Match Window Size");
+                ui.add_enabled(settings.match_window_size, egui::widgets::DragValue::new(&mut settings.resolution_scale).prefix("Scale: ").speed(0.01).clamp_range(0.1..=2));
+            });
+            egui::ComboBox::from_label("Show")
+                .selected_text(format!("{:?}", settings.display_mode))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut settings.display_mode, DisplayMode::Trail, "Trail");
+                    ui.selectable_value(&mut settings.display_mode, DisplayMode::RawTrail, "Raw Trail");
+                    ui.selectable_value(&mut settings.display_mode, DisplayMode::AgentDensity, "Agent Density");
+                    ui.selectable_value(&mut settings.display_mode, DisplayMode::Heading, "Heading");
+                    ui.selectable_value(&mut settings.display_mode, DisplayMode::SensorDisagreement, "Sensor Disagreement");
+                });
+            if settings.display_mode == DisplayMode::AgentDensity {
+                ui.add(egui::widgets::DragValue::new(&mut settings.density_scale).prefix("Density Scale: ").speed(0.05).clamp_range(1..=100));
+            }
+
+            ui.checkbox(&mut settings.agent_overlay, "Agent Overlay");
+            ui.add_enabled_ui(settings.agent_overlay, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut settings.overlay_style, OverlayStyle::Points, "Points");
+                    ui.selectable_value(&mut settings.overlay_style, OverlayStyle::Headings, "Headings");
+                    let [r, g, b, _] = settings.overlay_color.as_linear_rgba_f32();
+                    let mut rgb = [r, g, b];
+                    if egui::widgets::color_picker::color_edit_button_rgb(ui, &mut rgb).changed() {
+                        settings.overlay_color = Color::rgb_linear(rgb[0], rgb[1], rgb[2]);
+                    }
+                });
+                ui.add(egui::widgets::DragValue::new(&mut settings.overlay_size).prefix("Agent Size: ").speed(0.05).clamp_range(0.5..=20));
+                ui.add(egui::widgets::Slider::new(&mut settings.overlay_fraction, 0.001..=1.0).logarithmic(true).text("Drawn Fraction"));
+            });
+
+            ui.add(egui::widgets::DragValue::new(&mut settings.hdr_intensity).prefix("HDR Intensity: ").speed(0.01).clamp_range(0.1..=16));
+            ui.add(egui::widgets::DragValue::new(&mut settings.exposure).prefix("Exposure: ").suffix(" EV").speed(0.01).clamp_range(-8..=8));
+            ui.add(egui::widgets::DragValue::new(&mut settings.gamma).prefix("Gamma: ").speed(0.005).clamp_range(0.1..=4));
+            egui::ComboBox::from_label("Tone Mapping")
+                .selected_text(format!("{:?}", settings.tonemapping))
+                .show_ui(ui, |ui| {
+                    for tonemapping in TONEMAPPING_OPERATORS {
+                        ui.selectable_value(&mut settings.tonemapping, tonemapping, format!("{:?}", tonemapping));
+                    }
+                });
+            ui.add(egui::widgets::DragValue::new(&mut settings.bloom_intensity).prefix("Bloom: ").speed(0.005).clamp_range(0..=1));
+            ui.add_enabled(settings.bloom_intensity > 0.0, egui::widgets::DragValue::new(&mut settings.bloom_threshold).prefix("Bloom Threshold: ").speed(0.005).clamp_range(0..=4));
+        });
+
+        ui.collapsing("Checkpoints", |ui| {
+            ui.label(format!("Tick: {}", readback.lock().unwrap().tick));
+            if ui.button("Save Checkpoint").clicked() {
+                readback.lock().unwrap().checkpoint_requested = Some(export_path("checkpoint", CHECKPOINT_EXTENSION));
+            }
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut settings.checkpoint_path);
+                if ui.button("Load").clicked() && !settings.checkpoint_path.is_empty() {
+                    checkpoint_load.0 = Some(settings.checkpoint_path.clone());
+                }
+            });
+            ui.add(egui::widgets::DragValue::new(&mut settings.autosave_interval).prefix("Autosave Every: ").suffix(" ticks").speed(10.0).clamp_range(0..=1_000_000));
+            ui.checkbox(&mut settings.deterministic, "Fixed Time Step");
+            ui.add_enabled(settings.deterministic, egui::widgets::DragValue::new(&mut settings.time_step).prefix("Time Step: ").suffix("s").speed(0.0005).clamp_range(0.001..=0.1));
+            ui.add(egui::widgets::DragValue::new(&mut settings.seed).prefix("Seed: ").speed(1.0));
+        });
+
+        ui.collapsing("Export", |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut settings.agent_export_format, AgentExportFormat::Csv, "CSV");
+                ui.selectable_value(&mut settings.agent_export_format, AgentExportFormat::Npy, "NPY");
+                if ui.button("Export Agents").clicked() {
+                    readback.lock().unwrap().agents_requested = true;
+                }
+            });
+            ui.add(egui::widgets::DragValue::new(&mut settings.agent_export_interval).prefix("Export Agents Every: ").suffix(" ticks").speed(10.0).clamp_range(0..=1_000_000));
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut settings.trail_export_format, TrailExportFormat::Npy, "NPY");
+                ui.selectable_value(&mut settings.trail_export_format, TrailExportFormat::Raw, "Raw f32");
+                if ui.button("Export Trail").clicked() {
+                    readback.lock().unwrap().trail_requested = true;
+                }
+            });
+        });
+
+        ui.collapsing("Network", |ui| {
+            ui.add(egui::widgets::DragValue::new(&mut settings.network_threshold).prefix("Threshold: ").speed(0.005).clamp_range(0..=10));
+            ui.add(egui::widgets::DragValue::new(&mut settings.network_min_branch).prefix("Min Branch Length: ").speed(0.1).clamp_range(0..=500));
+            if ui.add_enabled(!network.extracting, egui::Button::new("Extract Network")).clicked() {
+                readback.lock().unwrap().network_requested = true;
+                network.extracting = true;
+            }
+            if let Some(graph) = &network.graph {
+                ui.label(format!("Nodes: {}, Edges: {}", graph.nodes.len(), graph.edges.len()));
+                ui.label(format!("Total Length: {:.0} texels", graph.total_length()));
+                ui.horizontal(|ui| {
+                    if ui.button("Export GraphML").clicked() {
+                        export_network(graph, NetworkExportFormat::GraphMl);
+                    }
+                    if ui.button("Export JSON").clicked() {
+                        export_network(graph, NetworkExportFormat::Json);
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.network_overlay, "Show Network");
+                let [r, g, b, _] = settings.network_color.as_linear_rgba_f32();
+                let mut rgb = [r, g, b];
+                if egui::widgets::color_picker::color_edit_button_rgb(ui, &mut rgb).changed() {
+                    settings.network_color = Color::rgb_linear(rgb[0], rgb[1], rgb[2]);
+                }
+            });
+        });
+
+        ui.collapsing("Statistics", |ui| {
+            ui.add(egui::widgets::DragValue::new(&mut settings.stats_interval).prefix("Interval: ").suffix(" ticks").speed(1.0).clamp_range(0..=10_000));
+            ui.add(egui::widgets::DragValue::new(&mut settings.stats_threshold).prefix("Coverage Threshold: ").speed(0.005).clamp_range(0..=10));
+            let statistics: [(&str, fn(&StatisticsSample) -> f32); 6] = [
+                ("Trail Mass", |sample| sample.trail_mass),
+                ("Mean Intensity", |sample| sample.mean_intensity),
+                ("Max Intensity", |sample| sample.max_intensity),
+                ("Coverage", |sample| sample.coverage),
+                ("Mean Speed", |sample| sample.mean_speed),
+                ("Heading Entropy", |sample| sample.heading_entropy),
+            ];
+            for (name, value) in statistics {
+                ui.label(format!("{}: {:.3}", name, history.latest().map_or(0.0, value)));
+                sparkline(ui, &history.series(value));
+            }
+        });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut settings.brush_tool, BrushTool::Trail, "Trail Brush");
+            ui.selectable_value(&mut settings.brush_tool, BrushTool::Agents, "Agent Brush");
+        });
+        match settings.brush_tool {
+            BrushTool::Trail => ui.label("[LMB] Attract, [RMB] Repel"),
+            BrushTool::Agents => ui.label("[LMB] Spawn, [RMB] Erase"),
+        };
+        ui.add(egui::widgets::DragValue::new(&mut settings.brush_radius).prefix("Brush Radius: ").speed(0.1).clamp_range(1..=500));
+        match settings.brush_tool {
+            BrushTool::Trail => ui.add(egui::widgets::DragValue::new(&mut settings.brush_strength).prefix("Brush Strength: ").speed(0.05).clamp_range(0..=100)),
+            BrushTool::Agents => ui.add(egui::widgets::DragValue::new(&mut settings.spawn_rate).prefix("Spawn Rate: ").suffix("/s").speed(100.0).clamp_range(0..=1_000_000)),
+        };
 
         ui.separator();
 
@@ -0,0 +1,589 @@
+use std::borrow::Cow;
+
+use bevy::{prelude::*, render::{extract_resource::ExtractResource, render_resource::{ShaderType, Buffer, UniformBuffer, BufferDescriptor, BufferUsages, Texture, TextureView, TextureDescriptor, TextureViewDescriptor, Extent3d, TextureDimension, TextureFormat, TextureUsages, BindGroup, BindGroupDescriptor, BindGroupEntry, BindingResource, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, StorageTextureAccess, TextureViewDimension, BufferBindingType, BufferSize, CachedComputePipelineId, PipelineCache, ComputePipelineDescriptor, CachedPipelineState, ComputePassDescriptor, Sampler, SamplerDescriptor, SamplerBindingType, FilterMode, TextureSampleType}, render_asset::RenderAssets, texture::FallbackImage, renderer::{RenderDevice, RenderQueue, RenderContext}, render_graph}};
+use bytemuck::{Pod, Zeroable};
+
+use super::{VOLUME_SIZE, VOLUME_AGENTS, VOLUME_DISPLAY_SIZE, VOLUME_WORKGROUP_SIZE, AGENTS_WORKGROUP_SIZE, TEX_WORKGROUP_SIZE, texture::{DisplayImage, SlimeMoldSprite}, ui::{UISettings, SimulationMode}, readback::{ReadbackBuffer, SimulationReadback}, gradient::GradientImage};
+
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct VolumeAgent {
+    pub position: [f32; 3],
+    /// Rotation of the sensor ring around the heading.
+    pub roll: f32,
+    pub direction: [f32; 3],
+    _padding: f32,
+}
+
+/// The image the volume is rendered into for display.
+#[derive(Resource, Clone, Deref, ExtractResource)]
+pub struct VolumeImage(pub Handle<Image>);
+
+pub fn setup_volume(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: VOLUME_DISPLAY_SIZE.0,
+            height: VOLUME_DISPLAY_SIZE.1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8Unorm,
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+
+    commands.insert_resource(VolumeImage(images.add(image)));
+}
+
+/// Shows the image of the current simulation mode on the sprite.
+pub fn update_display_image(
+    settings: Res<UISettings>,
+    display_image: Res<DisplayImage>,
+    volume_image: Res<VolumeImage>,
+    mut sprite_query: Query<&mut Handle<Image>, With<SlimeMoldSprite>>,
+) {
+    let image = match settings.mode {
+        SimulationMode::Flat | SimulationMode::Sphere => &display_image.0,
+        SimulationMode::Volume => &volume_image.0,
+    };
+
+    for mut handle in &mut sprite_query {
+        if *handle != *image {
+            *handle = image.clone();
+        }
+    }
+}
+
+
+/// Render world resources of the volume simulation.
+pub struct VolumeBuffers {
+    pub volume: Texture,
+    pub volume_view: TextureView,
+    /// Intermediate result of the volume diffusion.
+    pub temp: Texture,
+    pub temp_view: TextureView,
+    /// Fixed point trail deposits of the current step.
+    pub deposits: Buffer,
+    pub agents: Buffer,
+    pub readback: ReadbackBuffer,
+}
+
+impl VolumeBuffers {
+    pub fn new(device: &RenderDevice) -> Self {
+        let voxels = VOLUME_SIZE as u64 * VOLUME_SIZE as u64 * VOLUME_SIZE as u64;
+
+        let create_volume = || device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: VOLUME_SIZE,
+                height: VOLUME_SIZE,
+                depth_or_array_layers: VOLUME_SIZE,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let volume = create_volume();
+        let volume_view = volume.create_view(&TextureViewDescriptor::default());
+        let temp = create_volume();
+        let temp_view = temp.create_view(&TextureViewDescriptor::default());
+
+        let deposits = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: voxels * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let agents = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: VOLUME_AGENTS as u64 * std::mem::size_of::<VolumeAgent>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback = ReadbackBuffer::new(device, voxels * std::mem::size_of::<f32>() as u64);
+
+        Self {
+            volume,
+            volume_view,
+            temp,
+            temp_view,
+            deposits,
+            agents,
+            readback,
+        }
+    }
+}
+
+/// The volume takes about 256 MiB of GPU memory, so its resources only exist while the volume mode is selected.
+#[derive(Resource, Default)]
+pub struct VolumeResources(pub Option<VolumeBuffers>);
+
+/// Allocates the volume resources when the volume mode is selected and frees them when it is left, which restarts the volume simulation.
+pub fn prepare_volume_buffers(
+    device: Res<RenderDevice>,
+    settings: Res<UISettings>,
+    mut volume: ResMut<VolumeResources>,
+) {
+    match (settings.mode == SimulationMode::Volume, volume.0.is_some()) {
+        (true, false) => volume.0 = Some(VolumeBuffers::new(&device)),
+        (false, true) => volume.0 = None,
+        _ => {}
+    }
+}
+
+
+#[derive(Default, Clone, ShaderType)]
+pub struct VolumeSettingsUniform {
+    size: i32,
+    num_agents: u32,
+    delta_time: f32,
+    time: f32,
+
+    move_speed: f32,
+    turn_speed: f32,
+    trail_weight: f32,
+    decay_rate: f32,
+    diffuse_rate: f32,
+    sensor_angle_spacing: f32,
+    sensor_offset_dst: f32,
+    view: u32,
+
+    slice: f32,
+    view_angle: f32,
+    density: f32,
+}
+
+#[derive(Resource, Default)]
+pub struct VolumeSettingsBuffer {
+    pub buffer: UniformBuffer<VolumeSettingsUniform>,
+    /// Angle of the orbiting camera of the projection view.
+    view_angle: f32,
+}
+
+pub fn prepare_volume_settings(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut settings_buffer: ResMut<VolumeSettingsBuffer>,
+    settings: Res<UISettings>,
+    time: Res<Time>,
+) {
+    if settings.mode == SimulationMode::Volume {
+        settings_buffer.view_angle = (settings_buffer.view_angle + settings.volume_rotation_speed.to_radians() * time.delta_seconds()) % std::f32::consts::TAU;
+    }
+    let view_angle = settings_buffer.view_angle;
+
+    let buffer = settings_buffer.buffer.get_mut();
+    buffer.size = VOLUME_SIZE as i32;
+    buffer.num_agents = VOLUME_AGENTS;
+    buffer.delta_time = time.delta_seconds();
+    buffer.time = time.elapsed_seconds();
+    buffer.move_speed = settings.volume_move_speed;
+    buffer.turn_speed = settings.turn_speed;
+    buffer.trail_weight = settings.trail_weight;
+    buffer.decay_rate = settings.decay_rate;
+    buffer.diffuse_rate = settings.diffuse_rate;
+    buffer.sensor_angle_spacing = settings.sensor_angle_spacing;
+    buffer.sensor_offset_dst = settings.volume_sensor_offset;
+    buffer.view = settings.volume_view as u32;
+    buffer.slice = settings.volume_slice;
+    buffer.view_angle = view_angle;
+    buffer.density = settings.volume_density;
+
+    settings_buffer.buffer.write_buffer(&device, &queue);
+}
+
+pub fn readback_volume(
+    device: Res<RenderDevice>,
+    volume: Res<VolumeResources>,
+    readback: Res<SimulationReadback>,
+) {
+    let Some(volume_buf) = &volume.0 else { return };
+    if let Some(data) = volume_buf.readback.poll(&device) {
+        readback.lock().unwrap().volume = Some(data);
+    }
+}
+
+
+#[derive(Resource)]
+struct VolumeBindGroups(BindGroup, BindGroup, BindGroup);
+
+pub fn queue_volume_bind_group(
+    mut commands: Commands,
+    pipeline: Res<VolumePipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    volume_image: Res<VolumeImage>,
+    volume: Res<VolumeResources>,
+    volume_settings: Res<VolumeSettingsBuffer>,
+    gradient_image: Res<GradientImage>,
+    fallback_image: Res<FallbackImage>,
+    render_device: Res<RenderDevice>,
+) {
+    // The bind groups would keep freed volume resources alive.
+    let Some(volume_buf) = &volume.0 else {
+        commands.remove_resource::<VolumeBindGroups>();
+        return;
+    };
+    let view = &gpu_images[&volume_image.0];
+    let bind_group_volume = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.volume_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&volume_buf.volume_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&volume_buf.temp_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: volume_buf.deposits.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(&view.texture_view),
+            },
+        ],
+    });
+    let bind_group_agents = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.agents_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: volume_buf.agents.as_entire_binding(),
+        }],
+    });
+    let bind_group_settings = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.settings_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: volume_settings.buffer.binding().unwrap(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(gpu_images.get(&gradient_image.0).map_or(&fallback_image.d2.texture_view, |image| &image.texture_view)),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(&pipeline.gradient_sampler),
+            },
+        ],
+    });
+    commands.insert_resource(VolumeBindGroups(bind_group_volume, bind_group_agents, bind_group_settings));
+}
+
+#[derive(Resource)]
+pub struct VolumePipeline {
+    volume_bind_group_layout: BindGroupLayout,
+    agents_bind_group_layout: BindGroupLayout,
+    settings_bind_group_layout: BindGroupLayout,
+    gradient_sampler: Sampler,
+    init_agents_pipeline: CachedComputePipelineId,
+    init_volume_pipeline: CachedComputePipelineId,
+    update_agents_pipeline: CachedComputePipelineId,
+    diffuse_volume_pipeline: CachedComputePipelineId,
+    apply_volume_pipeline: CachedComputePipelineId,
+    render_volume_pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for VolumePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let volume_texture_entry = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadWrite,
+                format: TextureFormat::R32Float,
+                view_dimension: TextureViewDimension::D3,
+            },
+            count: None,
+        };
+        let volume_bind_group_layout =
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        volume_texture_entry(0),
+                        volume_texture_entry(1),
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Storage {
+                                    read_only: false,
+                                },
+                                has_dynamic_offset: false,
+                                min_binding_size: BufferSize::new(VOLUME_SIZE as u64 * VOLUME_SIZE as u64 * VOLUME_SIZE as u64 * std::mem::size_of::<u32>() as u64),
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::StorageTexture {
+                                access: StorageTextureAccess::WriteOnly,
+                                format: TextureFormat::Rgba8Unorm,
+                                view_dimension: TextureViewDimension::D2,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let agents_bind_group_layout =
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage {
+                                read_only: false,
+                            },
+                            has_dynamic_offset: false,
+                            min_binding_size: BufferSize::new(VOLUME_AGENTS as u64 * std::mem::size_of::<VolumeAgent>() as u64),
+                        },
+                        count: None,
+                    }],
+                });
+        let settings_bind_group_layout =
+            render_device
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Texture {
+                                sample_type: TextureSampleType::Float { filterable: true },
+                                view_dimension: TextureViewDimension::D2,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                });
+        let gradient_sampler = render_device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/slime_mold_volume.wgsl");
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let layout = vec![volume_bind_group_layout.clone(), agents_bind_group_layout.clone(), settings_bind_group_layout.clone()];
+        let queue_pipeline = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: None,
+                layout: layout.clone(),
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+
+        let init_agents_pipeline = queue_pipeline("initVolumeAgents");
+        let init_volume_pipeline = queue_pipeline("initVolume");
+        let update_agents_pipeline = queue_pipeline("updateVolumeAgents");
+        let diffuse_volume_pipeline = queue_pipeline("diffuseVolume");
+        let apply_volume_pipeline = queue_pipeline("applyVolume");
+        let render_volume_pipeline = queue_pipeline("renderVolume");
+
+        VolumePipeline {
+            volume_bind_group_layout,
+            agents_bind_group_layout,
+            settings_bind_group_layout,
+            gradient_sampler,
+            init_agents_pipeline,
+            init_volume_pipeline,
+            update_agents_pipeline,
+            diffuse_volume_pipeline,
+            apply_volume_pipeline,
+            render_volume_pipeline,
+        }
+    }
+}
+
+enum VolumeState {
+    Loading,
+    Init,
+    Waiting,
+    Update,
+}
+
+pub struct VolumeNode {
+    state: VolumeState,
+}
+
+impl Default for VolumeNode {
+    fn default() -> Self {
+        Self {
+            state: VolumeState::Loading,
+        }
+    }
+}
+
+impl render_graph::Node for VolumeNode {
+    fn update(&mut self, world: &mut World) {
+        let pipeline = world.resource::<VolumePipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let ready = |id| matches!(pipeline_cache.get_compute_pipeline_state(id), CachedPipelineState::Ok(_));
+        let allocated = world.resource::<VolumeResources>().0.is_some();
+        let active = world
+            .get_resource::<UISettings>()
+            .map_or(false, |settings| settings.running && settings.mode == SimulationMode::Volume);
+
+        // Freed resources are initialized again once they are allocated.
+        if !allocated {
+            self.state = VolumeState::Loading;
+        }
+
+        match self.state {
+            VolumeState::Loading => {
+                if allocated && ready(pipeline.init_agents_pipeline) && ready(pipeline.init_volume_pipeline) {
+                    self.state = VolumeState::Init;
+                }
+            }
+            VolumeState::Init => {
+                if ready(pipeline.update_agents_pipeline) && ready(pipeline.diffuse_volume_pipeline)
+                    && ready(pipeline.apply_volume_pipeline) && ready(pipeline.render_volume_pipeline)
+                {
+                    self.state = VolumeState::Waiting;
+                }
+            }
+            VolumeState::Waiting => {
+                if active {
+                    self.state = VolumeState::Update;
+                }
+            }
+            VolumeState::Update => {
+                if !active {
+                    self.state = VolumeState::Waiting;
+                }
+            }
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let (Some(bind_groups), Some(volume_buf)) = (world.get_resource::<VolumeBindGroups>(), &world.resource::<VolumeResources>().0) else { return Ok(()) };
+        let settings = world.resource::<UISettings>();
+        let readback = world.resource::<SimulationReadback>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline = world.resource::<VolumePipeline>();
+
+        let volume_workgroups = VOLUME_SIZE / VOLUME_WORKGROUP_SIZE;
+        let agent_workgroups = (VOLUME_AGENTS + AGENTS_WORKGROUP_SIZE - 1) / AGENTS_WORKGROUP_SIZE;
+
+        let encoder = render_context.command_encoder();
+        if let VolumeState::Init = self.state {
+            encoder.clear_buffer(&volume_buf.deposits, 0, None);
+        }
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
+
+            pass.set_bind_group(0, &bind_groups.0, &[]);
+            pass.set_bind_group(1, &bind_groups.1, &[]);
+            pass.set_bind_group(2, &bind_groups.2, &[]);
+
+            match self.state {
+                VolumeState::Loading => {}
+                VolumeState::Init => {
+                    let init_agents_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.init_agents_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(init_agents_pipeline);
+                    pass.dispatch_workgroups(agent_workgroups, 1, 1);
+
+                    let init_volume_pipeline = pipeline_cache
+                        .get_compute_pipeline(pipeline.init_volume_pipeline)
+                        .unwrap();
+                    pass.set_pipeline(init_volume_pipeline);
+                    pass.dispatch_workgroups(volume_workgroups, volume_workgroups, volume_workgroups);
+                }
+                VolumeState::Waiting | VolumeState::Update => {
+                    if let VolumeState::Update = self.state {
+                        let update_agents_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.update_agents_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(update_agents_pipeline);
+                        pass.dispatch_workgroups(agent_workgroups, 1, 1);
+
+                        let diffuse_volume_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.diffuse_volume_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(diffuse_volume_pipeline);
+                        pass.dispatch_workgroups(volume_workgroups, volume_workgroups, volume_workgroups);
+
+                        let apply_volume_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.apply_volume_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(apply_volume_pipeline);
+                        pass.dispatch_workgroups(volume_workgroups, volume_workgroups, volume_workgroups);
+                    }
+
+                    if settings.mode == SimulationMode::Volume {
+                        let render_volume_pipeline = pipeline_cache
+                            .get_compute_pipeline(pipeline.render_volume_pipeline)
+                            .unwrap();
+                        pass.set_pipeline(render_volume_pipeline);
+                        pass.dispatch_workgroups(VOLUME_DISPLAY_SIZE.0 / TEX_WORKGROUP_SIZE, VOLUME_DISPLAY_SIZE.1 / TEX_WORKGROUP_SIZE, 1);
+                    }
+                }
+            }
+        }
+
+        if let VolumeState::Waiting | VolumeState::Update = self.state {
+            let mut readback = readback.lock().unwrap();
+            if readback.volume_requested {
+                let size = Extent3d {
+                    width: VOLUME_SIZE,
+                    height: VOLUME_SIZE,
+                    depth_or_array_layers: VOLUME_SIZE,
+                };
+                if volume_buf.readback.copy_from_texture(encoder, &volume_buf.volume, size, VOLUME_SIZE * std::mem::size_of::<f32>() as u32) {
+                    readback.volume_requested = false;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}